@@ -1,56 +1,324 @@
-use crate::types::{FrameTime, Frame, SliceType};
+use crate::types::{
+    Curve, Envelope, Fade, FrameTime, Frame, Modifiers, PanLaw, Sample, SliceType
+};
 use crate::media::SoundMap;
-use std::collections::BTreeMap;
+use crate::media::onsets::DEFAULT_SENSITIVITY;
+use crate::resource::{Identifier, Registry};
+use crate::timeline::{Span, Timeline};
+use crate::io::osc::OscMessage;
+use std::collections::{HashMap, HashSet};
 
 /// An Event can currently be only a slice of a source.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Event {
-    src:         String,
+    src:         Identifier,
+    /// First frame of the part of the source that is played.
     slice_start: FrameTime,
-    slice_end:   FrameTime,
+    /// Length of the part of the source that is played. If the event
+    /// lasts longer, the part is played again from the beginning.
+    slice_len:   FrameTime,
+    /// Length of the source.
+    source_len:  FrameTime,
+    /// Whether parts which reach beyond either end of the source wrap
+    /// around to its other end, as in `|x+n|` and `|x-n|` slices.
+    /// Otherwise they are silent there.
+    wraps:       bool,
     duration:    FrameTime,
+    /// Whether the part is played backwards, from its end.
+    reverse:     bool,
+    /// How many frames the source keeps playing on for after the event,
+    /// while another one fades in.
+    tail:        FrameTime,
+    /// How the event fades in, if at all.
+    fade_in:     Option<Fade>,
+    /// How the event, including its tail, fades out, if at all.
+    fade_out:    Option<Fade>,
+    /// The envelope that shapes the event, if any.
+    /// Its release is played during the tail.
+    envelope:    Option<Envelope>,
+    /// What every sample of the event is multiplied by.
+    gain:        f64,
+    /// Where the event is placed between the left (-1)
+    /// and right (1) channels, if anywhere.
+    pan:         Option<f64>,
+    /// Whether the source is heard, resolved from the document's
+    /// muted and soloed sources whenever they change.
+    audible:     bool,
 }
 
 impl Event {
-    fn len (&self) -> FrameTime { self.duration }
+    /// Gets the identifier of the source.
+    pub fn src (&self) -> Identifier { self.src }
+    /// Gets the duration of the event.
+    pub fn len (&self) -> FrameTime { self.duration }
+    /// Gets the frame of the source that is heard `index` frames
+    /// after the event starts. During the tail, the source is played
+    /// on from where the event stopped.
+    pub fn offset (&self, index: FrameTime) -> FrameTime {
+        if index >= self.duration && self.duration > 0 {
+            let beyond = (index + 1 - self.duration) as i64;
+            let last = self.offset(self.duration - 1) as i64;
+            return self.inside(if self.reverse { last - beyond } else { last + beyond })
+        }
+        let index = index % self.slice_len.max(1);
+        let offset = if self.reverse {
+            self.slice_start + self.slice_len - 1 - index
+        } else {
+            self.slice_start + index
+        };
+        self.inside(offset as i64)
+    }
+    /// Wraps `frame` around the source if the event wraps, or else
+    /// moves it past the end of the source, where it is silent,
+    /// if it is before the start.
+    fn inside (&self, frame: i64) -> FrameTime {
+        if self.wraps {
+            wrap(frame, self.source_len)
+        } else if frame < 0 {
+            self.source_len
+        } else {
+            frame as FrameTime
+        }
+    }
+    /// Gets the level of the event `index` frames after it starts,
+    /// according to its gain, fades, and envelope.
+    pub fn level (&self, index: FrameTime) -> f64 {
+        let mut level = self.gain;
+        if let Some(fade) = self.fade_in {
+            level *= fade.curve.level(index as f64 / fade.len.max(1) as f64);
+        }
+        if let Some(fade) = self.fade_out {
+            let left = self.span().saturating_sub(index + 1);
+            level *= fade.curve.level(left as f64 / fade.len.max(1) as f64);
+        }
+        if let Some(envelope) = self.envelope {
+            level *= envelope.level(index, self.duration);
+        }
+        level
+    }
+}
+
+impl Span for Event {
+    fn span (&self) -> FrameTime { self.duration + self.tail }
+}
+
+/// The musical time of a document, set with the `!bpm` and `!meter`
+/// commands. Used to convert frames to bars and beats.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tempo {
+    /// Beats per minute.
+    pub bpm:           f64,
+    /// Beats per bar.
+    pub beats_per_bar: u32,
+}
+
+impl Default for Tempo {
+    fn default () -> Tempo {
+        Tempo { bpm: 120.0, beats_per_bar: 4 }
+    }
+}
+
+impl Tempo {
+    /// Ticks per beat.
+    pub const TICKS_PER_BEAT: f64 = 1920.0;
+    /// Gets the length of a beat in frames at sample `rate`.
+    pub fn beat_frames (&self, rate: usize) -> f64 {
+        rate as f64 * 60.0 / self.bpm
+    }
+    /// Gets the length of a bar in frames at sample `rate`.
+    pub fn bar_frames (&self, rate: usize) -> f64 {
+        self.beat_frames(rate) * self.beats_per_bar as f64
+    }
+    /// Gets the bar, beat (both counted from 1) and tick at `frame`.
+    pub fn bbt (&self, frame: FrameTime, rate: usize) -> (usize, usize, usize) {
+        let beats = frame as f64 / self.beat_frames(rate);
+        let beats_per_bar = self.beats_per_bar.max(1) as usize;
+        let whole = beats.floor() as usize;
+        let tick = (beats.fract() * Self::TICKS_PER_BEAT) as usize;
+        (whole / beats_per_bar + 1, whole % beats_per_bar + 1, tick)
+    }
+}
+
+/// A region of the document which is played `count` times in a row,
+/// or forever if `count` is `None`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Loop {
+    /// First frame of the region.
+    pub start: FrameTime,
+    /// Frame after the last frame of the region.
+    pub end:   FrameTime,
+    /// How many times the region is played.
+    pub count: Option<usize>,
+}
+
+impl Loop {
+    fn len (&self) -> FrameTime { self.end - self.start }
 }
 
 /// A Lude **document** is a text string describing
 /// a temporal sequence of **events**. It is generated by
 /// calling `eval(read(&src))`, where `&src` points to
 /// a piece of **source code**.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Document {
     /// A map of the source data used in this document.
     media:      SoundMap,
 
+    /// Everything the document is made from.
+    pub resources: Registry,
+
     /// The length of the document. Is this actually used?
     pub length: FrameTime,
 
-    /// A map of what events start at which moment.
-    pub events: BTreeMap<FrameTime, Vec<Event>>,
+    /// What events start at which moment.
+    pub events: Timeline<Event>,
+
+    /// Tempo of the document, if one was set.
+    pub tempo:  Option<Tempo>,
+
+    /// How far slice bounds may be moved to zero crossings of the source,
+    /// in frames, set with the `!snap` command; 0 if they are not moved.
+    pub snap:   FrameTime,
+
+    /// For how many frames a slice that starts where the previous one
+    /// of the same source ends overlaps with it, set with the `!crossfade`
+    /// command; 0 if they do not overlap.
+    pub crossfade: FrameTime,
 
-    /// Longest event in document.
-    /// Used to determine the document's actual last frame.
-    longest:    FrameTime,
+    /// How panned events are spread between the channels,
+    /// set with the `!panlaw` command.
+    pub pan_law: PanLaw,
+
+    /// How readily onsets are detected in sources, from 0 to 1,
+    /// set in percent with the `!sensitivity` command.
+    pub sensitivity: f64,
+
+    /// Named points in time, set with the sync command.
+    pub markers: HashMap<String, FrameTime>,
+
+    /// Regions that are repeated during playback, ordered by start.
+    /// Event times are given as if there were no loops;
+    /// `unloop` converts from playback time to event time.
+    pub loops:  Vec<Loop>,
+
+    /// Messages sent to other programs during playback,
+    /// by the moment they are sent.
+    pub messages: Timeline<OscMessage>,
+
+    /// Sources that are not heard.
+    muted:      HashSet<String>,
+
+    /// Sources that are heard, if any; all others are not.
+    soloed:     HashSet<String>,
+
+    /// Where the last slice of each source stopped reading it, by path.
+    /// `|+n|` and `|-n|` slices carry on from there.
+    heads:      HashMap<String, FrameTime>,
+
+    /// Number of output channels, counted as events are added.
+    channels:   usize,
+}
+
+impl Default for Document {
+    fn default () -> Document {
+        Document::new()
+    }
 }
 
 impl Document {
+    /// The settings that only change how events are written,
+    /// and so do nothing once the document has been evaluated.
+    pub const WRITE_SETTINGS: [&str; 3] = ["sensitivity", "snap", "crossfade"];
     /// Creates an empty document.
     pub fn new () -> Document {
         Document {
             media:   SoundMap::new(),
+            resources: Registry::new(),
             length:  0,
-            events:  BTreeMap::new(),
-            longest: 0
+            events:  Timeline::new(),
+            tempo:   None,
+            snap:    0,
+            crossfade: 0,
+            pan_law: PanLaw::ThreeDb,
+            sensitivity: DEFAULT_SENSITIVITY,
+            markers: HashMap::new(),
+            loops:   Vec::new(),
+            messages: Timeline::new(),
+            muted:   HashSet::new(),
+            soloed:  HashSet::new(),
+            heads:   HashMap::new(),
+            channels: 0
+        }
+    }
+    /// Sets the document-wide setting `name` to `value`.
+    /// These are the settings that the `!NAME NUMBER` command can change.
+    pub fn set (&mut self, name: &str, value: f64) -> Result<(), String> {
+        match name {
+            "bpm" if value > 0.0 =>
+                self.tempo.get_or_insert_with(Tempo::default).bpm = value,
+            "meter" if value >= 1.0 =>
+                self.tempo.get_or_insert_with(Tempo::default).beats_per_bar = value as u32,
+            "sensitivity" if (0.0..=100.0).contains(&value) =>
+                self.sensitivity = value / 100.0,
+            "snap" if value >= 0.0 =>
+                self.snap = value as FrameTime,
+            "crossfade" if value >= 0.0 =>
+                self.crossfade = value as FrameTime,
+            "panlaw" if value == 0.0 => self.pan_law = PanLaw::ZeroDb,
+            "panlaw" if value == 3.0 => self.pan_law = PanLaw::ThreeDb,
+            "panlaw" if value == 6.0 => self.pan_law = PanLaw::SixDb,
+            "bpm" | "meter" | "sensitivity" | "snap" | "crossfade" | "panlaw" =>
+                return Err(format!("invalid value for {}: {}", name, value)),
+            _ => return Err(format!("unknown setting {}", name))
+        }
+        Ok(())
+    }
+    /// Points the resource `src` to the file at `path`, so that every
+    /// event which uses it plays that file instead.
+    pub fn relink (&mut self, src: Identifier, path: &str) -> Result<(), String> {
+        self.resources.relink(src, path, &self.media)?;
+        self.channels = 0;
+        for (_, event) in self.events.iter() {
+            self.channels = self.channels.max(event_channels(&self.resources, event));
+        }
+        self.resolve_audible();
+        Ok(())
+    }
+    /// Mutes the sources in `muted` and, if there are any, hears only
+    /// the sources in `soloed`. Both may contain paths or names.
+    pub fn mute (&mut self, muted: HashSet<String>, soloed: HashSet<String>) {
+        self.muted = muted;
+        self.soloed = soloed;
+        self.resolve_audible();
+    }
+    /// Marks every event as heard or not, according to `audible`.
+    fn resolve_audible (&mut self) {
+        let audible: Vec<bool> = self.resources.list().map(|(id, _)| self.audible(id)).collect();
+        for (_, event) in self.events.iter_mut() {
+            event.audible = audible[event.src.0];
+        }
+    }
+    /// Whether the source `src` is heard, according to `muted`
+    /// and `soloed`, which may contain its path or its name.
+    pub fn audible (&self, src: Identifier) -> bool {
+        let name = &self.resources.get(src).name;
+        let path = self.resources.path(src);
+        let named = |set: &HashSet<String>| {
+            set.contains(name) || (!path.is_empty() && set.contains(path))
+        };
+        if self.soloed.is_empty() {
+            !named(&self.muted)
+        } else {
+            named(&self.soloed)
         }
     }
     /// Takes the source at `src`, creates a slice with type `slice_type`
     /// from the source data between `slice_start` and `slice_end`, and
-    /// writes it at position `at` in the document.
+    /// writes it at position `at` in the document, changed by `modifiers`.
+    /// If `modifiers` divide the source, the bounds are counted in parts.
+    /// `|+n|` and `|-n|` slices without a start carry on from where
+    /// the last slice of the same source stopped.
     /// Returns the duration of the slice.
-    /// If the document's `longest` field is less than the duration of
-    /// the slice, it is set to the duration of the slice.
     pub fn write (
         &mut         self,
         at:          FrameTime,
@@ -58,58 +326,343 @@ impl Document {
         slice_type:  SliceType,
         slice_start: Option<FrameTime>,
         slice_end:   Option<FrameTime>,
+        modifiers:   Modifiers,
     ) -> FrameTime {
-        // bump longest duration.
-        // used for determining the range of frame times
-        // that may contain events that matter in a frame
         //println!("{} {:?} {:?}", &src, &slice_start, &slice_end);
         let src_len = self.media.get_source_length(src);
-        let (slice_start, slice_end, duration) = match slice_type {
-            SliceType::Full => (0, src_len, src_len),
+        let (slice_start, slice_end) = match modifiers.division {
+            None => (slice_start, slice_end),
+            Some(0) => panic!("can not divide a source into 0 parts"),
+            Some(parts) => {
+                let part = |index: FrameTime| match slice_type {
+                    SliceType::Abs => part(index, parts, src_len),
+                    _ => index * src_len / parts
+                };
+                (slice_start.map(part), slice_end.map(part))
+            }
+        };
+        let after_end = |start: FrameTime| {
+            if start > src_len { panic!("slice |{}:| starts after the source", start) }
+            start
+        };
+        // where the part starts in the source, how long it is,
+        // and whether it is played backwards
+        let (slice_start, slice_len, reverse) = match slice_type {
+            SliceType::Full => (0, src_len, false),
             SliceType::Abs => match (slice_start, slice_end) {
-                (Some(start), Some(end)) => (start, end, abs_sub(start, end)),
-                (Some(start), None     ) => (start, src_len, src_len - start),
-                (None,        Some(end)) => (0, end, end),
-                (None,        None     ) => (0, src_len, src_len)
+                (Some(start), Some(end)) if end < start => (end, start - end, false),
+                (Some(start), Some(end)) => (start, end - start, false),
+                (Some(start), None     ) => (start, src_len - after_end(start), false),
+                (None,        Some(end)) => (0, end, false),
+                (None,        None     ) => (0, src_len, false)
             },
-            SliceType::Fwd => match slice_start {
-                None => panic!("fwd slice must be |x+n|"),
-                Some(start) => match slice_end {
-                    Some(len) => (start, start + len, len),
-                    None => panic!("fwd slice must be |x+n|")
-                }
+            SliceType::Fwd => match slice_end {
+                Some(len) => (slice_start.unwrap_or_else(|| self.head(src)), len, false),
+                None => panic!("fwd slice must be |x+n| or |+n|")
             },
-            SliceType::Rew => match slice_start {
-                None => panic!("rew slice must be |x-n|"),
-                Some(start) => match slice_end {
-                    Some(len) => (start, start - len, len),
-                    None => panic!("rew slice must be |x-n|")
-                }
+            SliceType::Rew => match slice_end {
+                Some(len) => {
+                    let start = slice_start.unwrap_or_else(|| self.head(src));
+                    (wrap(start as i64 - len as i64, src_len), len, true)
+                },
+                None => panic!("rew slice must be |x-n| or |-n|")
             }
         };
-        let src = src.to_string();
-        self.add_event(at, Event { src, slice_start, slice_end, duration });
+        let reverse = reverse != modifiers.reverse;
+        let wraps = matches!(slice_type, SliceType::Fwd | SliceType::Rew);
+        let slice_start = if wraps { wrap(slice_start as i64, src_len) } else { slice_start };
+        let (slice_start, slice_len) = self.snap(src, slice_start, slice_len, &modifiers);
+        let head = if reverse { slice_start } else { slice_start + slice_len };
+        self.heads.insert(src.to_string(), wrap(head as i64, src_len));
+        let src = self.resources.register_file(src, &self.media);
+        self.add_event(at, Event {
+            src,
+            slice_start,
+            slice_len,
+            source_len: src_len,
+            wraps,
+            duration: slice_len,
+            reverse,
+            tail:     modifiers.envelope.map_or(0, |envelope| envelope.release),
+            fade_in:  modifiers.fade_in,
+            fade_out: modifiers.fade_out,
+            envelope: modifiers.envelope,
+            gain:     modifiers.gain,
+            pan:      modifiers.pan,
+            audible:  true
+        });
+        slice_len
+    }
+    /// Writes pieces of the source at `src`, one after the other from
+    /// position `at`: each `(start, len, count)` in `pieces` writes the
+    /// `len` frames from `start` `count` times. If `modifiers` divide the
+    /// source, `start` and `len` are counted in parts. The pieces are
+    /// written in reverse order, and each of them backwards, if `modifiers`
+    /// say so. Returns the duration of all of them.
+    pub fn write_pieces (
+        &mut       self,
+        at:        FrameTime,
+        src:       &str,
+        pieces:    &[(FrameTime, FrameTime, FrameTime)],
+        modifiers: Modifiers,
+    ) -> FrameTime {
+        let src_len = self.media.get_source_length(src);
+        let mut pieces: Vec<(FrameTime, FrameTime, FrameTime)> = pieces.iter()
+            .map(|(start, len, count)| match modifiers.division {
+                None => (*start, *len, *count),
+                Some(parts) => {
+                    let start_frame = part(*start, parts, src_len);
+                    (start_frame, part(start + len, parts, src_len) - start_frame, *count)
+                }
+            })
+            .map(|(start, len, count)| {
+                let (start, len) = self.snap(src, start, len, &modifiers);
+                (start, len, count)
+            })
+            .collect();
+        if modifiers.reverse { pieces.reverse() }
+        if let Some((start, len, _)) = pieces.last() {
+            let head = if modifiers.reverse { *start } else { start + len };
+            self.heads.insert(src.to_string(), wrap(head as i64, src_len));
+        }
+        let src = self.resources.register_file(src, &self.media);
+        let mut duration = 0;
+        for (start, len, count) in pieces {
+            self.add_event(at + duration, Event {
+                src,
+                slice_start: start,
+                slice_len:   len,
+                source_len:  src_len,
+                wraps:       false,
+                duration:    len * count,
+                reverse:     modifiers.reverse,
+                tail:        modifiers.envelope.map_or(0, |envelope| envelope.release),
+                fade_in:     modifiers.fade_in,
+                fade_out:    modifiers.fade_out,
+                envelope:    modifiers.envelope,
+                gain:        modifiers.gain,
+                pan:         modifiers.pan,
+                audible:     true
+            });
+            duration += len * count;
+        }
         duration
     }
-    fn add_event (&mut self, at: FrameTime, event: Event) {
+    /// Moves the bounds of the `len` frames from `start` in the source
+    /// at `src` to the nearest zero crossings, if `modifiers` or the
+    /// `snap` setting say so. Returns the new start and length.
+    fn snap (
+        &self, src: &str, start: FrameTime, len: FrameTime, modifiers: &Modifiers
+    ) -> (FrameTime, FrameTime) {
+        let window = match modifiers.snap {
+            Some(false) => 0,
+            Some(true) if self.snap == 0 => DEFAULT_SNAP_WINDOW,
+            _ => self.snap
+        };
+        let src_len = self.media.get_source_length(src);
+        if window == 0 || len < 2 { return (start, len) }
+        let snap = |frame: FrameTime| match frame {
+            0 => 0,
+            frame => self.media.zero_crossing(src, frame, window)
+        };
+        let end = wrap((start + len) as i64, src_len);
+        let (snapped_start, snapped_end) = (snap(start), snap(end));
+        let snapped_len = len as i64
+            + (snapped_end as i64 - end as i64)
+            - (snapped_start as i64 - start as i64);
+        if snapped_len < 1 { return (start, len) }
+        (snapped_start, snapped_len as FrameTime)
+    }
+    /// Gets where the hits in the source at `src` start,
+    /// as detected with the current `sensitivity`.
+    pub fn onsets (&self, src: &str) -> Vec<FrameTime> {
+        self.media.onsets(src, self.sensitivity)
+    }
+    /// Gets where hit `index` of the source at `src` starts,
+    /// and where it ends: at the next hit, or the end of the source.
+    pub fn hit (&self, src: &str, index: usize) -> (FrameTime, FrameTime) {
+        let onsets = self.onsets(src);
+        match onsets.get(index) {
+            Some(start) => (*start, onsets.get(index + 1).cloned()
+                .unwrap_or_else(|| self.source_length(src))),
+            None => panic!("no onset #{} in {}, which has {}", index, src, onsets.len())
+        }
+    }
+    /// Gets the length of the source at `src`, in frames.
+    pub fn source_length (&self, src: &str) -> FrameTime {
+        self.media.get_source_length(src)
+    }
+    /// Gets the position of the cue point `label` in the source at `src`,
+    /// as read from the file when the document first used it.
+    pub fn cue (&self, src: &str, label: &str) -> FrameTime {
+        match self.media.cues(src).iter().find(|cue| cue.name == label) {
+            Some(cue) => cue.position as FrameTime,
+            None => panic!("no cue point named {} in {}", label, src)
+        }
+    }
+    /// Writes the source at `src` from its cue point `label` up to the
+    /// next cue point, or to its end, at position `at` in the document.
+    /// Returns the duration of the slice.
+    pub fn write_cue (&mut self, at: FrameTime, src: &str, label: &str) -> FrameTime {
+        let start = self.cue(src, label);
+        let end = self.media.cues(src).iter()
+            .map(|cue| cue.position as FrameTime)
+            .filter(|position| *position > start)
+            .min();
+        self.write(at, src, SliceType::Abs, Some(start), end, Modifiers::default())
+    }
+    /// Gets where the last slice of the source at `src` stopped reading it,
+    /// or its start if none has been written yet.
+    pub fn head (&self, src: &str) -> FrameTime {
+        self.heads.get(src).cloned().unwrap_or(0)
+    }
+    /// Sends `message` to other programs when playback reaches `at`.
+    pub fn send (&mut self, at: FrameTime, message: OscMessage) {
+        self.messages.add(at, message);
+    }
+    /// Repeats the region between `start` and `end` `count` times,
+    /// or forever if `count` is `None`. Loops must not overlap.
+    pub fn add_loop (&mut self, start: FrameTime, end: FrameTime, count: Option<usize>) {
+        if end <= start { panic!("loop must not be empty ({}..{})", start, end) }
+        let index = self.loops.iter().position(|l| l.start > start)
+            .unwrap_or(self.loops.len());
+        self.loops.insert(index, Loop { start, end, count });
+    }
+    /// Converts a point in playback time, where loops are repeated,
+    /// to the point in the document that is heard at that time.
+    pub fn unloop (&self, time: FrameTime) -> FrameTime {
+        // how much later than written everything after a loop is heard
+        let mut shift = 0;
+        for l in self.loops.iter() {
+            let start = l.start + shift;
+            if time < start { break }
+            match l.count {
+                None => return l.start + (time - start) % l.len(),
+                Some(count) => {
+                    if time < start + count * l.len() {
+                        return l.start + (time - start) % l.len()
+                    }
+                    shift += count.saturating_sub(1) * l.len();
+                }
+            }
+        }
+        time - shift
+    }
+    /// Gets the points in playback time at which the point `time`
+    /// of the document is heard; the inverse of `unloop`.
+    /// Returns `None` if it is inside or after an endless loop.
+    pub fn replay (&self, time: FrameTime) -> Option<Vec<FrameTime>> {
+        let mut shift = 0;
+        for l in self.loops.iter() {
+            if time < l.start { break }
+            let count = l.count?;
+            if time < l.end {
+                return Some((0..count).map(|i| time + shift + i * l.len()).collect())
+            }
+            shift += count.saturating_sub(1) * l.len();
+        }
+        Some(vec![time + shift])
+    }
+    /// Gets the first point in playback time, at or after `after`,
+    /// at which the point `time` of the document is heard, if any.
+    /// Unlike `replay`, this works inside endless loops as well.
+    pub fn replay_from (&self, time: FrameTime, after: FrameTime) -> Option<FrameTime> {
+        let mut shift = 0;
+        for l in self.loops.iter() {
+            if time < l.start { break }
+            let first = time + shift;
+            match l.count {
+                None if time < l.end => {
+                    let laps = after.saturating_sub(first).div_ceil(l.len());
+                    return Some(first + laps * l.len())
+                },
+                None => return None,
+                Some(count) if time < l.end => {
+                    return (0..count).map(|i| first + i * l.len()).find(|t| *t >= after)
+                },
+                Some(count) => shift += count.saturating_sub(1) * l.len()
+            }
+        }
+        Some(time + shift).filter(|t| *t >= after)
+    }
+    /// Gets the last frame in playback time, with loops repeated,
+    /// or `None` if there is an endless loop.
+    pub fn duration (&self) -> Option<FrameTime> {
+        let mut end = self.loops.iter().map(|l| l.end).max().unwrap_or(0);
+        if !self.events.is_empty() {
+            let (_, max, longest) = self.bounds();
+            end = end.max(max + longest);
+        }
+        if let Some(last) = self.messages.last() {
+            end = end.max(last);
+        }
+        let mut shift = 0;
+        for l in self.loops.iter() {
+            shift += l.count?.saturating_sub(1) * l.len();
+        }
+        Some(end + shift)
+    }
+    /// Adds `event` at `at`. With `crossfade` set, if an event of the same
+    /// source ends at `at`, it plays on under `event` while they crossfade.
+    fn add_event (&mut self, at: FrameTime, mut event: Event) {
         eprintln!("add_event {}", &at);
-        match self.events.get_mut(&at) {
-            None => {
-                let mut events = Vec::new();
-                events.push(event);
-                self.events.insert(at, events);
-            },
-            Some(events) => events.push(event),
+        self.channels = self.channels.max(event_channels(&self.resources, &event));
+        event.audible = self.audible(event.src);
+        let crossfade = self.crossfade;
+        if crossfade > 0 && event.fade_in.is_none() && event.duration >= crossfade {
+            let src = event.src;
+            let fade = Fade { len: crossfade, curve: Curve::Sine };
+            let ends_here = |start: FrameTime, previous: &Event| previous.src == src
+                && previous.tail == 0
+                && previous.fade_out.is_none()
+                && previous.duration >= crossfade
+                && start + previous.duration == at;
+            let mut starts: Vec<FrameTime> = self.events.overlapping(at.saturating_sub(1))
+                .filter(|(start, previous)| ends_here(*start, previous))
+                .map(|(start, _)| start)
+                .collect();
+            starts.dedup();
+            for start in starts.iter() {
+                for mut previous in self.events.remove(*start, |previous| ends_here(*start, previous)) {
+                    previous.tail = crossfade;
+                    previous.fade_out = Some(fade);
+                    self.events.add(*start, previous);
+                }
+            }
+            if !starts.is_empty() { event.fade_in = Some(fade) }
         }
+        self.events.add(at, event)
     }
     /// Gets start, end, and longest event.
     pub fn bounds (&self) -> (FrameTime, FrameTime, FrameTime) {
-        let min = *self.events.keys().next().unwrap();
-        let max = *self.events.keys().next_back().unwrap();
-        let longest = self.longest;
+        let min = self.events.first().unwrap();
+        let max = self.events.last().unwrap();
+        let longest = self.events.longest();
         (min, max, longest)
     }
-    /// Gets the summed frame at index N.
+    /// Gets the number of output channels, which is the highest
+    /// channel count among the sources used in the document,
+    /// and at least 2 if any event is panned.
+    pub fn channels (&self) -> usize {
+        self.channels
+    }
+    /// Places `frame` at `pan` between the left and right channels,
+    /// according to `pan_law`. A mono frame becomes a stereo one.
+    fn pan (&self, frame: Frame, pan: f64) -> Frame {
+        let (left, right) = self.pan_law.levels(pan);
+        let level = |sample: Sample, level: f64| (sample as f64 * level).round() as Sample;
+        match frame.len() {
+            0 => frame,
+            1 => vec![level(frame[0], left), level(frame[0], right)],
+            _ => frame.iter().enumerate().map(|(channel, sample)| match channel {
+                0 => level(*sample, left),
+                1 => level(*sample, right),
+                _ => *sample
+            }).collect()
+        }
+    }
+    /// Gets the summed frame at index N. Sources with fewer
+    /// channels than the document are repeated over the rest.
     pub fn get_frame (&self, frame_index: FrameTime) -> Option<Frame> {
         // nothing if document is empty
         if self.events.len() == 0 { return None }
@@ -123,26 +676,64 @@ impl Document {
         // nothing if document is empty
 
         // maybe something in the middle?
+        let channels = self.channels();
         let mut event_frames = Vec::new();
-        let start = if frame_index < longest { 0 } else { frame_index - longest };
-        let event_range = self.events.range(start..frame_index+1);
-        for (event_start, events) in event_range {
+        for (event_start, event) in self.events.overlapping(frame_index) {
             let event_frame_index = frame_index - event_start;
-            for event in events {
-                let offset = event.slice_start;
-                let index = (event_frame_index + offset) as i64;
-                if index as FrameTime > event.slice_end { continue }
-                //println!("{}={:?}[{}]", &frame_index, &event.src, &index);
-                match self.media.get_frame(&event.src, index) {
-                    Some(frame) => event_frames.push(frame),
-                    _ => {}
-                }
-            }
+            if event_frame_index >= event.span() { continue }
+            if !event.audible { continue }
+            let index = event.offset(event_frame_index) as i64;
+            //println!("{}={:?}[{}]", &frame_index, &event.src, &index);
+            let frame = self.media.get_frame(self.resources.path(event.src), index);
+            let frame = scale(frame, event.level(event_frame_index));
+            let frame = match event.pan {
+                Some(pan) => self.pan(frame, pan),
+                None => frame
+            };
+            event_frames.push(spread(frame, channels))
         }
         sum_subframes(event_frames)
     }
 }
 
+/// Gets how many output channels `event` needs: as many as its source
+/// has, and at least 2 if it is panned.
+fn event_channels (resources: &Registry, event: &Event) -> usize {
+    let channels = resources.get(event.src).channels;
+    if event.pan.is_some() { channels.max(2) } else { channels }
+}
+
+/// How far slice bounds are moved to zero crossings by the `snap`
+/// modifier if the `!snap` command has not said otherwise (10 ms).
+const DEFAULT_SNAP_WINDOW: FrameTime = 441;
+
+/// Gets where part `index` starts when a source of length `len`
+/// is divided into `parts` equal parts.
+fn part (index: FrameTime, parts: FrameTime, len: FrameTime) -> FrameTime {
+    if parts == 0 { panic!("can not divide a source into 0 parts") }
+    if index > parts { panic!("|/{}| slice has no part {}", parts, index) }
+    index * len / parts
+}
+
+/// Wraps `frame` around a source of length `len`,
+/// so that it is always inside the source.
+fn wrap (frame: i64, len: FrameTime) -> FrameTime {
+    frame.rem_euclid(len.max(1) as i64) as FrameTime
+}
+
+/// Repeats the channels of `frame` over `channels` channels,
+/// so that e.g. a mono frame is heard in all of them.
+fn spread (frame: Frame, channels: usize) -> Frame {
+    if frame.is_empty() || frame.len() >= channels { return frame }
+    (0..channels).map(|channel| frame[channel % frame.len()]).collect()
+}
+
+/// Multiplies every sample of `frame` by `level`.
+fn scale (frame: Frame, level: f64) -> Frame {
+    if (level - 1.0).abs() < f64::EPSILON { return frame }
+    frame.into_iter().map(|sample| (sample as f64 * level).round() as Sample).collect()
+}
+
 fn sum_subframes (event_frames: Vec<Frame>) -> Option<Frame> {
     let mut frame: Frame = Vec::new();
     for event_frame in event_frames.iter() {
@@ -159,11 +750,3 @@ fn sum_subframes (event_frames: Vec<Frame>) -> Option<Frame> {
         None
     }
 }
-
-fn abs_sub (x: FrameTime, y: FrameTime) -> FrameTime {
-    if x >= y {
-        x - y
-    } else {
-        y - x
-    }
-}
@@ -1,5 +1,6 @@
 use crate::document::Document;
-use crate::types::{FrameTime, SliceType};
+use crate::io::osc::{OscArg, OscMessage};
+use crate::types::{Curve, Envelope, Fade, FrameTime, Modifiers, SliceType};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::time::Instant;
@@ -25,6 +26,28 @@ pub fn read (source: &str) -> Pair<Rule> {
     parsed
 }
 
+/// Points every **path** in `source` that is `from` to `to` instead,
+/// leaving the rest of the source code as it is.
+pub fn relink (source: &str, from: &str, to: &str) -> Result<String, String> {
+    let valid = match DefaultParser::parse(Rule::Path, to) {
+        Ok(mut pairs) => pairs.next().map(|pair| pair.as_str()) == Some(to),
+        Err(_) => false
+    };
+    if !valid { return Err(format!("{} is not a valid path", to)) }
+    let mut relinked = String::new();
+    let mut last = 0;
+    for pair in read(source).into_inner().flatten() {
+        if pair.as_rule() == Rule::Path && pair.as_str() == from {
+            let span = pair.as_span();
+            relinked.push_str(&source[last..span.start()]);
+            relinked.push_str(to);
+            last = span.end();
+        }
+    }
+    relinked.push_str(&source[last..]);
+    Ok(relinked)
+}
+
 /// A collection of **statements** is **evaluated**, returning a `Document` -
 /// a full, unambiguous description of what **slices** should be
 /// **rendered** to the **output**.
@@ -45,25 +68,63 @@ pub fn read (source: &str) -> Pair<Rule> {
 /// * The **jump** command (`@NUMBER`) sets the cursor to `NUMBER`.
 /// * The **skip** command (`@+NUMBER`) moves the cursor forward by `NUMBER`.
 /// * The **back** command (`@-NUMBER`) mobes the cursor back by `NUMBER`.
-/// * The **sync** command gives a **name** to the current value of the cursor,
+/// * The **sync** command (`#NAME`) gives a **name** to the current value of the cursor,
 ///   so that you can reference a point in time by a name rather than a number.
 ///   It is equivalent ot an alias (see below)
 /// * **TODO** use `/` and `*` for speeding up/slowing down
-/// * **TODO** add something for repetition
+///
+/// ### Loop
+/// Everything written between `{` and `}` is a **loop**, which is played
+/// twice. Writing `}*NUMBER` plays it `NUMBER` times (at least once),
+/// and `}*` plays it forever. The cursor is not affected: whatever comes
+/// after the loop is heard after the last repetition. Loops can not be
+/// nested, and every `{` needs a `}`.
 ///
 /// ### Source
 /// Stating a **path** to a **source** makes that source **active**.
 ///
 /// * **TODO** Paths are evaluated relative to the location of the source file.
 ///
+/// ### Cue
+/// Writing `>NAME` writes the active source from its **cue point** `NAME`
+/// up to its next cue point, or to its end, and moves the cursor past it.
+/// Cue points are read from the source file, e.g. from the `cue ` and
+/// `labl` chunks of a WAV file.
+///
+/// ### Envelope
+/// Declaring `NAME = adsr ATTACK DECAY SUSTAIN% RELEASE` makes an
+/// envelope, with its times in frames. Writing `~NAME` in a slice
+/// shapes it with that envelope; writing `~NAME` on its own shapes
+/// every following slice of the active source that does not say
+/// otherwise. E.g. `stab = adsr 100 2000 40% 4410 ./pad.wav ~stab`
+/// turns slices of a pad into stabs.
 ///
 /// ### Name
 /// Assignment is of the form `NAME = [CONTENT]`.
 /// Afterwards, writing `NAME` is equivalent to writing `CONTENT`.
 ///
 /// ### Command
-/// **TODO** use `!` for commands to the renderer (such as setting sample rate,
-/// mixing algorithm, etc)
+/// Commands to the renderer are of the form `!NAME NUMBER`.
+///
+/// * `!bpm NUMBER` sets the tempo of the document in beats per minute.
+/// * `!meter NUMBER` sets the number of beats per bar.
+/// * `!snap NUMBER` moves the bounds of every slice after it to zero
+///   crossings of the source, at most `NUMBER` frames away (0 stops it).
+/// * `!crossfade NUMBER` overlaps every slice that follows another one
+///   of the same source by `NUMBER` frames, fading from one to the other
+///   (0 stops it).
+/// * `!panlaw NUMBER` sets by how many dB a source panned to the center
+///   with `pan=` is turned down: 0, 3 (the default) or 6.
+/// * `!sensitivity NUMBER` sets how readily the hits in sources are
+///   detected for `|#n|` slices, from 0 to 100 (the default is 50).
+/// * **TODO** use `!` for setting sample rate, mixing algorithm, etc
+///
+/// ### Message
+/// Binding is of the form `NAME=/OSC/ADDRESS`. Afterwards, writing
+/// `NAME NUMBER...` sends an [OSC](http://opensoundcontrol.org/) message
+/// to `/OSC/ADDRESS`, with the numbers as arguments, when playback reaches
+/// the cursor. The cursor is not affected. Messages are only sent by
+/// `lude play --osc-target HOST:PORT`.
 ///
 /// ### Alter
 /// **TODO**
@@ -82,7 +143,14 @@ struct Eval<'i> {
     doc:     RefCell<Document>,
     cursor:  RefCell<FrameTime>,
     source:  RefCell<String>,
-    markers: RefCell<HashMap<String, FrameTime>>
+    markers: RefCell<HashMap<String, FrameTime>>,
+    /// Where the open loop starts, in time and in the source code.
+    looping: RefCell<Option<(FrameTime, (usize, usize))>>,
+    bound:   RefCell<HashMap<String, String>>,
+    /// Envelopes, by name.
+    envelopes: RefCell<HashMap<String, Envelope>>,
+    /// Envelopes that shape every slice of a source, by path.
+    shapes:  RefCell<HashMap<String, Envelope>>
 }
 
 impl Eval<'_> {
@@ -92,7 +160,11 @@ impl Eval<'_> {
             doc:     RefCell::new(Document::new()),
             cursor:  RefCell::new(0),
             source:  RefCell::new(String::new()),
-            markers: RefCell::new(HashMap::new())
+            markers: RefCell::new(HashMap::new()),
+            looping: RefCell::new(None),
+            bound:   RefCell::new(HashMap::new()),
+            envelopes: RefCell::new(HashMap::new()),
+            shapes:  RefCell::new(HashMap::new())
         }
     }
     pub fn run (&self) -> Document {
@@ -100,21 +172,39 @@ impl Eval<'_> {
         for statement in parsed.into_inner() {
             match statement.as_rule() {
                 Rule::Comment => {},
+                Rule::Command => self.command(statement),
                 Rule::Jump   => self.jump(statement.into_inner().next().unwrap()),
                 Rule::Skip   => self.skip(statement.into_inner().next().unwrap()),
                 Rule::Back   => self.back(statement.into_inner().next().unwrap()),
                 Rule::Sync   => self.sync(statement.into_inner().next().unwrap()),
+                Rule::LoopStart => self.loop_start(statement),
+                Rule::LoopEnd   => self.loop_end(statement),
                 Rule::Source => self.source(statement),
-                Rule::Slice  => self.slice(statement),
+                Rule::Slices => statement.into_inner().for_each(|slice| self.slice(slice)),
+                Rule::Cue    => self.cue(statement),
+                Rule::Shape  => self.shape(statement),
+                Rule::Declare => self.declare(statement),
+                Rule::Bind   => self.bind(statement),
                 Rule::Assign => self.assign(statement),
                 Rule::Alias  => self.alias(statement),
                 _ => unreachable!(),
             };
         }
+        if let Some((_, (line, column))) = self.looping.replace(None) {
+            panic!("loop at line {}, column {} is never closed", line, column)
+        }
         let mut doc = self.doc.replace(Document::new()); // how do i drop
         doc.length = *self.cursor.borrow();
+        doc.markers = self.markers.replace(HashMap::new());
         doc
     }
+    fn command (&self, command: Pair<Rule>) {
+        let mut inner = command.into_inner();
+        let name = inner.next().unwrap().as_str();
+        let value = pair_to_frame_time(inner.next().unwrap());
+        self.doc.borrow_mut().set(name, value as f64)
+            .unwrap_or_else(|e| panic!("!{}: {}", name, e))
+    }
     fn jump (&self, time: Pair<Rule>) {
         let time = pair_to_frame_time(time);
         self.cursor.replace(time);
@@ -134,6 +224,28 @@ impl Eval<'_> {
         let name = name.as_str().to_string();
         self.markers.borrow_mut().insert(name, *self.cursor.borrow());
     }
+    fn loop_start (&self, loop_start: Pair<Rule>) {
+        let cursor = *self.cursor.borrow();
+        let (line, column) = loop_start.as_span().start_pos().line_col();
+        if self.looping.replace(Some((cursor, (line, column)))).is_some() {
+            panic!("loops can not be nested (line {}, column {})", line, column)
+        }
+    }
+    fn loop_end (&self, loop_end: Pair<Rule>) {
+        let (line, column) = loop_end.as_span().start_pos().line_col();
+        let (start, _) = self.looping.replace(None).unwrap_or_else(|| panic!(
+            "loop end without loop start (line {}, column {})", line, column
+        ));
+        let count = match loop_end.into_inner().next() {
+            None => Some(2),
+            Some(repeat) => repeat.into_inner().next().map(pair_to_frame_time)
+        };
+        if count == Some(0) {
+            panic!("loop can not be played 0 times (line {}, column {})", line, column)
+        }
+        let cursor = *self.cursor.borrow();
+        self.doc.borrow_mut().add_loop(start, cursor, count);
+    }
     fn source (&self, path: Pair<Rule>) {
         let path = path.as_str().to_string();
         self.source.replace(path);
@@ -142,30 +254,180 @@ impl Eval<'_> {
         let mut slice_type  = SliceType::Full;
         let mut slice_start = None;
         let mut slice_end   = None;
-        for pair in slice.into_inner().flatten() {
+        let mut frames      = Vec::new();
+        let mut modifiers   = Modifiers::default();
+        for pair in slice.into_inner() {
+            if modifiers.division.is_some() && pair.clone().into_inner().flatten()
+                .any(|inner| matches!(inner.as_rule(),
+                    Rule::CueName | Rule::Percent | Rule::FromEnd | Rule::Onset))
+            {
+                panic!("slices divided into parts can only use part numbers: {}", pair.as_str())
+            }
             match pair.as_rule() {
-                Rule::SliceStart => slice_start = Some(pair_to_frame_time(pair)),
-                Rule::SliceEnd => slice_end = Some(pair_to_frame_time(pair)),
+                Rule::Division => modifiers.division =
+                    Some(pair_to_frame_time(pair.into_inner().next().unwrap())),
+                Rule::SliceStart => slice_start = Some(self.slice_point(pair)),
+                Rule::SliceEnd => slice_end = Some(pair),
+                Rule::SliceFrame => frames.push(self.slice_frame(pair)),
                 Rule::SliceAbs => slice_type = SliceType::Abs,
                 Rule::SliceFwd => slice_type = SliceType::Fwd,
                 Rule::SliceRew => slice_type = SliceType::Rew,
+                Rule::Reverse => modifiers.reverse = !modifiers.reverse,
+                Rule::Snap => modifiers.snap = Some(true),
+                Rule::NoSnap => modifiers.snap = Some(false),
+                Rule::FadeIn => modifiers.fade_in = Some(fade(pair)),
+                Rule::FadeOut => modifiers.fade_out = Some(fade(pair)),
+                Rule::Shape => modifiers.envelope = Some(self.envelope(pair)),
+                Rule::Gain => {
+                    let db: f64 = pair.as_str().trim_end_matches("dB").parse().unwrap();
+                    modifiers.gain *= 10f64.powf(db / 20.0)
+                },
+                Rule::Scale => modifiers.gain *= pair.as_str()[1..].parse::<f64>().unwrap(),
+                Rule::Pan => {
+                    let pan: f64 = pair.as_str()["pan=".len()..].parse().unwrap();
+                    if !(-1.0..=1.0).contains(&pan) {
+                        panic!("pan must be between -1 and 1, not {}", pan)
+                    }
+                    modifiers.pan = Some(pan)
+                },
                 _ => unreachable!()
             }
         }
+        if modifiers.envelope.is_none() {
+            modifiers.envelope = self.shapes.borrow().get(&*self.source.borrow()).cloned();
+        }
         let cursor = *self.cursor.borrow();
-        let advance = self.doc.borrow_mut().write(
-            cursor, &self.source.borrow(), slice_type, slice_start, slice_end
-        );
+        let advance = if frames.is_empty() {
+            let slice_end = slice_end.map(|pair| match slice_type {
+                SliceType::Fwd | SliceType::Rew => match pair.clone().into_inner().next() {
+                    Some(inner) if !matches!(inner.as_rule(), Rule::Frames | Rule::Percent) =>
+                        panic!("slice length must be a number, not {}", pair.as_str()),
+                    _ => self.slice_point(pair)
+                },
+                _ => self.slice_point(pair)
+            });
+            self.doc.borrow_mut().write(
+                cursor, &self.source.borrow(), slice_type, slice_start, slice_end, modifiers
+            )
+        } else {
+            self.doc.borrow_mut().write_pieces(cursor, &self.source.borrow(), &frames, modifiers)
+        };
         self.cursor.replace_with(|cursor| *cursor + advance);
     }
+    /// Gets where the piece of the source that a `|x|` or `|x_n|` slice
+    /// writes starts, how long it is, and how many times it is repeated.
+    /// The piece is one frame, or one part in a divided slice,
+    /// or a whole hit if `x` is an onset.
+    fn slice_frame (&self, frame: Pair<Rule>) -> (FrameTime, FrameTime, FrameTime) {
+        let mut inner = frame.into_inner();
+        let point = inner.next().unwrap();
+        let (start, len) = match point.clone().into_inner().next() {
+            Some(onset) if onset.as_rule() == Rule::Onset => {
+                let index = pair_to_frame_time(onset.into_inner().next().unwrap());
+                let (start, end) = self.doc.borrow().hit(&self.source.borrow(), index);
+                (start, end - start)
+            },
+            _ => (self.slice_point(point), 1)
+        };
+        let repeats = inner.next()
+            .map(|repeats| pair_to_frame_time(repeats.into_inner().next().unwrap()))
+            .unwrap_or(1);
+        (start, len, repeats)
+    }
+    /// Gets the frame that a slice boundary stands for: a number,
+    /// a percentage of the active source, a number of frames before
+    /// its end, one of its onsets, or the name of one of its cue points.
+    fn slice_point (&self, pair: Pair<Rule>) -> FrameTime {
+        let inner = pair.into_inner().next().unwrap();
+        let doc = self.doc.borrow();
+        let source = self.source.borrow();
+        match inner.as_rule() {
+            Rule::Percent => {
+                let percent: f64 = inner.as_str().trim_end_matches('%').parse().unwrap();
+                (doc.source_length(&source) as f64 * percent / 100.0).round() as FrameTime
+            },
+            Rule::FromEnd => {
+                let before_end: FrameTime = inner.as_str()[1..].parse().unwrap();
+                doc.source_length(&source).checked_sub(before_end).unwrap_or_else(||
+                    panic!("{} is before the start of {}", inner.as_str(), source))
+            },
+            Rule::Frames => pair_to_frame_time(inner),
+            Rule::Onset =>
+                doc.hit(&source, pair_to_frame_time(inner.into_inner().next().unwrap())).0,
+            _ => doc.cue(&source, inner.as_str())
+        }
+    }
+    fn cue (&self, cue: Pair<Rule>) {
+        let label = cue.into_inner().next().unwrap().as_str();
+        let cursor = *self.cursor.borrow();
+        let advance = self.doc.borrow_mut().write_cue(cursor, &self.source.borrow(), label);
+        self.cursor.replace_with(|cursor| *cursor + advance);
+    }
+    /// Shapes every following slice of the active source
+    /// with the envelope named in a `~NAME` statement.
+    fn shape (&self, shape: Pair<Rule>) {
+        let envelope = self.envelope(shape);
+        self.shapes.borrow_mut().insert(self.source.borrow().clone(), envelope);
+    }
+    /// Gets the envelope named in `~NAME`.
+    fn envelope (&self, shape: Pair<Rule>) -> Envelope {
+        let name = shape.into_inner().next().unwrap().as_str();
+        match self.envelopes.borrow().get(name) {
+            Some(envelope) => *envelope,
+            None => panic!("no envelope named {}", name)
+        }
+    }
+    fn declare (&self, declare: Pair<Rule>) {
+        let mut inner = declare.into_inner();
+        let name = inner.next().unwrap().as_str().to_string();
+        let attack = pair_to_frame_time(inner.next().unwrap());
+        let decay = pair_to_frame_time(inner.next().unwrap());
+        let sustain = inner.next().unwrap().as_str();
+        let release = pair_to_frame_time(inner.next().unwrap());
+        let sustain = match sustain.trim_end_matches('%').parse::<f64>() {
+            Ok(percent) if percent <= 100.0 => percent / 100.0,
+            _ => panic!("sustain of {} must be at most 100%, not {}", name, sustain)
+        };
+        self.envelopes.borrow_mut().insert(name, Envelope { attack, decay, sustain, release });
+    }
+    fn bind (&self, bind: Pair<Rule>) {
+        let mut inner = bind.into_inner();
+        let name = inner.next().unwrap().as_str().to_string();
+        let address = inner.next().unwrap().as_str().to_string();
+        self.bound.borrow_mut().insert(name, address);
+    }
     fn assign (&self, _assign: Pair<Rule>) {
         panic!("not implemented")
     }
-    fn alias (&self, _name: Pair<Rule>) {
-        panic!("not implemented")
+    fn alias (&self, alias: Pair<Rule>) {
+        let mut inner = alias.into_inner();
+        let name = inner.next().unwrap().as_str();
+        let address = match self.bound.borrow().get(name) {
+            Some(address) => address.clone(),
+            None => panic!("not implemented: {} is not bound to an address", name)
+        };
+        let args = inner.map(|arg| {
+            let arg = arg.as_str();
+            match arg.parse::<i32>() {
+                Ok(value) => OscArg::Int(value),
+                Err(_) => OscArg::Float(arg.parse().unwrap())
+            }
+        }).collect();
+        let cursor = *self.cursor.borrow();
+        self.doc.borrow_mut().send(cursor, OscMessage::new(&address, args));
     }
 }
 
+/// Gets the length and curve of a `<n` or `>n` fade.
+fn fade (fade: Pair<Rule>) -> Fade {
+    let mut inner = fade.into_inner();
+    let len = pair_to_frame_time(inner.next().unwrap());
+    let curve = inner.next()
+        .map(|curve| Curve::from_name(curve.as_str()).unwrap())
+        .unwrap_or(Curve::Linear);
+    Fade { len, curve }
+}
+
 fn pair_to_frame_time (pair: Pair<Rule>) -> FrameTime {
-    FrameTime::from_str_radix(pair.as_str(), 10).unwrap()
+    FrameTime::from_str_radix(pair.as_str().trim(), 10).unwrap()
 }
@@ -0,0 +1,71 @@
+//! drive the playback engine with simulated cycles, without a sound server
+
+use std::thread::sleep;
+use std::time::Duration;
+use crate::types::FrameTime;
+use super::engine::{Backend, Player};
+
+/// A backend that calls the `Player` like an audio server would,
+/// and keeps what it was given instead of sending it to a device.
+#[derive(Debug)]
+pub struct DummyBackend {
+    /// Frames per second.
+    pub sample_rate: usize,
+    /// Frames per cycle.
+    pub buffer_size: FrameTime,
+    /// If set, wait for one period between cycles, like a sound card would.
+    pub realtime:    bool,
+    /// If set, stop after this many cycles even if playback is not finished.
+    pub max_cycles:  Option<usize>,
+    /// Everything that was output, one `Vec` per channel.
+    pub output:      Vec<Vec<f32>>,
+    /// Number of cycles run so far.
+    pub cycles:      usize,
+}
+
+impl DummyBackend {
+    /// Creates a backend that runs cycles of `buffer_size` frames
+    /// as fast as possible.
+    pub fn new (sample_rate: usize, buffer_size: FrameTime) -> DummyBackend {
+        DummyBackend {
+            sample_rate,
+            buffer_size,
+            realtime:   false,
+            max_cycles: None,
+            output:     Vec::new(),
+            cycles:     0,
+        }
+    }
+    /// Runs a single cycle of `player` and records its output.
+    pub fn cycle (&mut self, player: &mut Player) {
+        let channels = player.channels();
+        while self.output.len() < channels {
+            self.output.push(vec![0.0; self.cycles * self.buffer_size]);
+        }
+        let mut buffer = vec![0.0; self.buffer_size];
+        player.process(self.buffer_size);
+        for (channel, output) in self.output.iter_mut().enumerate() {
+            player.write_channel(channel, &mut buffer);
+            output.extend_from_slice(&buffer);
+        }
+        self.cycles += 1;
+    }
+}
+
+impl Backend for DummyBackend {
+    fn sample_rate (&self) -> usize {
+        self.sample_rate
+    }
+    fn run (&mut self, mut player: Player) {
+        let period = Duration::from_micros(
+            self.buffer_size as u64 * 1_000_000 / self.sample_rate.max(1) as u64
+        );
+        while !player.status().finished() {
+            if let Some(max_cycles) = self.max_cycles {
+                if self.cycles >= max_cycles { break }
+            }
+            self.cycle(&mut player);
+            if self.realtime { sleep(period) }
+        }
+    }
+}
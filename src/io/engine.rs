@@ -0,0 +1,547 @@
+//! realtime playback core, independent of the audio backend
+//!
+//! A worker thread pre-renders a `Playable` into a lock-free ring buffer
+//! through a `Renderer`. Once per cycle, the audio backend calls the
+//! `Player`, which only copies samples out of the ring buffer: it never
+//! allocates, locks, or waits for the worker. When the worker falls
+//! behind, the missing frames are played as silence, counted as an
+//! underrun, and skipped once they arrive, so that playback stays in time.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread::{Builder as ThreadBuilder, JoinHandle, sleep};
+use std::time::Duration;
+use crate::document::Document;
+use crate::types::{FrameTime, SAMPLE_RATE};
+use crate::timeline::Span;
+use crate::media::sampler::Sampler;
+use super::osc::OscMessage;
+use super::ring::{ring, Producer, Consumer};
+
+/// How many frames are rendered ahead of the playhead by default.
+pub const DEFAULT_LATENCY: FrameTime = 8192;
+
+/// The largest number of frames that the `Player` outputs in one cycle.
+pub const MAX_CYCLE_FRAMES: FrameTime = 8192;
+
+/// Something that can be rendered by the playback engine.
+pub trait Playable: Send {
+    /// Number of output channels.
+    fn channels (&self) -> usize;
+    /// Number of frames until the end.
+    fn len (&self) -> FrameTime;
+    /// Writes the frame at `index` into `frame`, one sample per channel.
+    fn frame (&self, index: FrameTime, frame: &mut [f32]);
+    /// Gets the first point at or after `frame` where playback
+    /// can switch over to something else, as per `quantize`,
+    /// when it is played at sample `rate`.
+    fn safe_point (&self, frame: FrameTime, _quantize: Quantize, _rate: usize) -> FrameTime {
+        frame
+    }
+    /// Gets the messages to send to other programs at `index`.
+    fn messages (&self, _index: FrameTime) -> Vec<OscMessage> {
+        Vec::new()
+    }
+}
+
+/// A message that is due when the player reaches `frame`,
+/// unless it has located somewhere else since `epoch`.
+#[derive(Debug)]
+pub struct Cue {
+    /// When to send the message.
+    pub frame:   FrameTime,
+    /// How many times the player had located when the message was rendered.
+    pub epoch:   usize,
+    /// What to send.
+    pub message: OscMessage,
+}
+
+impl Span for Cue {}
+
+/// Where a new version of what is playing takes over from the old one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quantize {
+    /// As soon as possible.
+    Block,
+    /// At the start of the next bar, if there is a tempo.
+    Bar,
+    /// At the next marker, if there is one.
+    Marker,
+}
+
+/// A new version of what is playing, sent to the renderer
+/// to take over without stopping playback.
+#[derive(Debug)]
+pub struct Swap<P> {
+    /// What to play from now on.
+    pub playable: P,
+    /// Where to switch over.
+    pub quantize: Quantize,
+}
+
+impl Playable for Document {
+    fn channels (&self) -> usize {
+        Document::channels(self).max(1)
+    }
+    fn len (&self) -> FrameTime {
+        if self.events.is_empty() && self.messages.is_empty() { return 0 }
+        match self.duration() {
+            Some(duration) => duration + 1,
+            None => FrameTime::MAX
+        }
+    }
+    fn frame (&self, index: FrameTime, frame: &mut [f32]) {
+        for sample in frame.iter_mut() { *sample = 0.0 }
+        if let Some(samples) = self.get_frame(self.unloop(index)) {
+            for (sample, value) in frame.iter_mut().zip(samples.iter()) {
+                *sample = *value as f32 / 32768.0;
+            }
+        }
+    }
+    fn safe_point (&self, frame: FrameTime, quantize: Quantize, rate: usize) -> FrameTime {
+        match quantize {
+            Quantize::Block => frame,
+            Quantize::Bar => match self.tempo {
+                None => frame,
+                Some(tempo) => {
+                    let bar = tempo.bar_frames(rate);
+                    ((frame as f64 / bar).ceil() * bar) as FrameTime
+                }
+            },
+            Quantize::Marker => self.markers.values()
+                .filter_map(|marker| self.replay_from(*marker, frame))
+                .min()
+                .unwrap_or(frame)
+        }
+    }
+    fn messages (&self, index: FrameTime) -> Vec<OscMessage> {
+        match self.messages.get(self.unloop(index)) {
+            Some(messages) => messages.clone(),
+            None => Vec::new()
+        }
+    }
+}
+
+/// An audio backend calls the `Player` once per cycle.
+pub trait Backend {
+    /// Frames per second.
+    fn sample_rate (&self) -> usize;
+    /// Runs `player` once per cycle until it reports that it has finished.
+    fn run (&mut self, player: Player);
+}
+
+/// Playback state, shared between the worker, the player and the caller.
+#[derive(Debug, Default)]
+pub struct Status {
+    position:  AtomicUsize,
+    underruns: AtomicUsize,
+    rendered:  AtomicBool,
+    finished:  AtomicBool,
+    stopped:   AtomicBool,
+    // set by the caller to steer the player when it is not
+    // following an external transport
+    paused:    AtomicBool,
+    cue:       AtomicUsize,
+    cued:      AtomicBool,
+    // set by the player to ask the renderer to move to `seek`
+    seek:      AtomicUsize,
+    epoch:     AtomicUsize,
+    // set by the renderer once it has emptied the ring buffer
+    // and started rendering from the new position
+    ack:       AtomicUsize,
+}
+
+impl Status {
+    /// The frame that will be played next.
+    pub fn position (&self) -> FrameTime {
+        self.position.load(Ordering::Acquire)
+    }
+    /// Number of cycles in which the ring buffer ran dry.
+    pub fn underruns (&self) -> usize {
+        self.underruns.load(Ordering::Acquire)
+    }
+    /// Whether the worker has rendered everything.
+    pub fn rendered (&self) -> bool {
+        self.rendered.load(Ordering::Acquire)
+    }
+    /// Whether the player has output everything.
+    pub fn finished (&self) -> bool {
+        self.finished.load(Ordering::Acquire)
+    }
+    /// Whether playback has ended.
+    pub fn stopped (&self) -> bool {
+        self.stopped.load(Ordering::Acquire)
+    }
+    /// How many times the player has located.
+    pub fn epoch (&self) -> usize {
+        self.epoch.load(Ordering::Acquire)
+    }
+    /// Whether the player is moving forward.
+    pub fn rolling (&self) -> bool {
+        !self.paused.load(Ordering::Acquire)
+    }
+    /// Makes the player move forward from where it is.
+    pub fn start (&self) {
+        self.paused.store(false, Ordering::Release)
+    }
+    /// Makes the player stay where it is and output silence.
+    pub fn stop (&self) {
+        self.paused.store(true, Ordering::Release)
+    }
+    /// Makes the player continue from `position` in its next cycle.
+    pub fn locate (&self, position: FrameTime) {
+        self.cue.store(position, Ordering::Release);
+        self.cued.store(true, Ordering::Release);
+    }
+    /// Ends playback: the worker stops rendering.
+    pub fn end (&self) {
+        self.stopped.store(true, Ordering::Release)
+    }
+}
+
+/// The non-realtime end: renders a `Playable` into the ring buffer.
+pub struct Renderer<P: Playable> {
+    playable: P,
+    swaps:    Option<Receiver<Swap<P>>>,
+    next:     Option<(FrameTime, P)>,
+    cues:     Option<Sender<Cue>>,
+    producer: Producer,
+    position: FrameTime,
+    epoch:    usize,
+    frame:    Vec<f32>,
+    status:   Arc<Status>,
+    /// Frames per second, to quantize swaps to bars.
+    rate:     usize,
+}
+
+impl<P: Playable> Renderer<P> {
+    /// Starts taking new versions of what is playing from `swaps`.
+    pub fn swap_from (&mut self, swaps: Receiver<Swap<P>>) {
+        self.swaps = Some(swaps);
+    }
+    /// Starts sending the messages of what is playing to `cues`,
+    /// as they are rendered.
+    pub fn cue_to (&mut self, cues: Sender<Cue>) {
+        self.cues = Some(cues);
+    }
+    /// Renders as many frames as fit in the ring buffer.
+    /// Returns the number of frames rendered.
+    ///
+    /// Frames that are already in the ring buffer stay as they are,
+    /// so a new version of what is playing is heard after at most
+    /// one ring buffer's worth of frames, or later if quantized.
+    pub fn fill (&mut self) -> FrameTime {
+        let channels = self.frame.len();
+        let mut rendered = 0;
+        self.receive();
+        loop {
+            let epoch = self.status.epoch.load(Ordering::Acquire);
+            if epoch != self.epoch {
+                self.epoch = epoch;
+                self.position = self.status.seek.load(Ordering::Acquire);
+                self.status.rendered.store(false, Ordering::Release);
+                // the player does not pop until it gets the ack
+                self.producer.clear();
+                self.status.ack.store(epoch, Ordering::Release);
+                // nothing has been rendered from here yet, so
+                // there is no reason to wait with swapping
+                if let Some((_, playable)) = self.next.take() {
+                    self.playable = playable;
+                }
+            }
+            let swap_now = match self.next {
+                Some((at, _)) => at <= self.position,
+                None => false
+            };
+            if swap_now {
+                let (_, playable) = self.next.take().unwrap();
+                self.playable = playable;
+                self.status.rendered.store(false, Ordering::Release);
+                eprintln!("swapped at frame {}", self.position);
+            }
+            // past the end, keep rendering silence until the swap
+            if self.position >= self.playable.len() && self.next.is_none() {
+                self.status.rendered.store(true, Ordering::Release);
+                break
+            }
+            if self.producer.free() < channels { break }
+            self.playable.frame(self.position, &mut self.frame);
+            self.producer.push(&self.frame);
+            if let Some(cues) = &self.cues {
+                for message in self.playable.messages(self.position) {
+                    let cue = Cue { frame: self.position, epoch: self.epoch, message };
+                    let _ = cues.send(cue);
+                }
+            }
+            self.position += 1;
+            rendered += 1;
+        }
+        rendered
+    }
+    /// Takes the latest swap, if any, and schedules it
+    /// at the next safe point of what is currently playing.
+    fn receive (&mut self) {
+        let swaps = match &self.swaps {
+            Some(swaps) => swaps,
+            None => return
+        };
+        if let Some(swap) = swaps.try_iter().last() {
+            let at = self.playable.safe_point(self.position, swap.quantize, self.rate);
+            self.next = Some((at, swap.playable));
+        }
+    }
+}
+
+/// The realtime end: copies frames from the ring buffer to the backend.
+#[derive(Debug)]
+pub struct Player {
+    consumer: Consumer,
+    channels: usize,
+    latency:  FrameTime,
+    scratch:  Vec<f32>,
+    frames:   FrameTime,
+    position: FrameTime,
+    head:     FrameTime,
+    epoch:    usize,
+    locating: bool,
+    sampler:  Option<Sampler>,
+    status:   Arc<Status>,
+}
+
+impl Player {
+    /// Number of output channels.
+    pub fn channels (&self) -> usize {
+        self.channels
+    }
+    /// Shared playback state.
+    pub fn status (&self) -> &Arc<Status> {
+        &self.status
+    }
+    /// Mixes whatever `sampler` plays into the output,
+    /// whether or not the transport is rolling.
+    pub fn mix (&mut self, sampler: Sampler) {
+        self.sampler = Some(sampler);
+    }
+    /// Pulls the next `n_frames` frames out of the ring buffer,
+    /// to be copied to the outputs with `write_channel`.
+    ///
+    /// Follows `Status::start`, `Status::stop` and `Status::locate`.
+    pub fn process (&mut self, n_frames: FrameTime) {
+        if self.status.cued.swap(false, Ordering::AcqRel) {
+            self.position = self.status.cue.load(Ordering::Acquire);
+        }
+        let rolling = self.status.rolling();
+        self.process_at(self.position, n_frames, rolling)
+    }
+    /// Pulls `n_frames` frames starting at `position`, as set by an external
+    /// transport. If the transport is not `rolling`, outputs silence.
+    ///
+    /// Frames which were played as silence during an underrun are dropped
+    /// as soon as they arrive, to stay in time. If the transport moves
+    /// elsewhere, the renderer is asked to start over from there, and the
+    /// output is silent until it does.
+    pub fn process_at (
+        &mut self, position: FrameTime, n_frames: FrameTime, rolling: bool
+    ) {
+        self.pull(position, n_frames, rolling);
+        if let Some(sampler) = &mut self.sampler {
+            sampler.process(n_frames.min(MAX_CYCLE_FRAMES));
+        }
+    }
+    fn pull (&mut self, position: FrameTime, n_frames: FrameTime, rolling: bool) {
+        let channels = self.channels;
+        let frames = n_frames.min(MAX_CYCLE_FRAMES);
+        let wanted = frames * channels;
+        for sample in self.scratch[..wanted].iter_mut() {
+            *sample = 0.0
+        }
+        self.frames = frames;
+        self.position = if rolling { position + n_frames } else { position };
+        self.status.position.store(self.position, Ordering::Release);
+
+        if self.locating {
+            if self.status.ack.load(Ordering::Acquire) != self.epoch { return }
+            self.locating = false;
+        }
+        if !rolling {
+            if position != self.head { self.locate(position) }
+            return
+        }
+        if position < self.head || position > self.head + self.latency {
+            self.locate(position);
+            return
+        }
+
+        // must be read before looking at the ring, so that if it is set,
+        // everything that was rendered is already in the ring
+        let rendered = self.status.rendered();
+
+        if position > self.head {
+            let skipped = self.consumer.skip((position - self.head) * channels);
+            self.head += skipped / channels;
+            if self.head < position {
+                self.ran_dry(rendered, position);
+                return
+            }
+        }
+        let popped = self.consumer.pop(&mut self.scratch[..wanted]);
+        self.head += popped / channels;
+        if popped < wanted {
+            self.ran_dry(rendered, position + frames);
+        }
+    }
+    /// Copies one channel of the frames pulled by `process` into `output`.
+    pub fn write_channel (&self, channel: usize, output: &mut [f32]) {
+        for (index, sample) in output.iter_mut().enumerate() {
+            *sample = if index < self.frames && channel < self.channels {
+                self.scratch[index * self.channels + channel]
+            } else {
+                0.0
+            }
+        }
+        if let Some(sampler) = &self.sampler {
+            sampler.add_channel(channel, output)
+        }
+    }
+    fn ran_dry (&mut self, rendered: bool, position: FrameTime) {
+        if rendered && self.consumer.is_empty() {
+            self.head = position;
+            self.status.finished.store(true, Ordering::Release);
+        } else {
+            self.status.underruns.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+    fn locate (&mut self, position: FrameTime) {
+        self.epoch = self.epoch.wrapping_add(1);
+        self.head = position;
+        self.locating = true;
+        self.status.finished.store(false, Ordering::Release);
+        self.status.seek.store(position, Ordering::Release);
+        self.status.epoch.store(self.epoch, Ordering::Release);
+    }
+}
+
+/// Creates the two ends of the engine around a ring buffer
+/// that holds `latency` frames, sharing `status` with the caller.
+pub fn prepare<P: Playable> (
+    playable: P, latency: FrameTime, status: Arc<Status>
+) -> (Renderer<P>, Player) {
+    let channels = playable.channels();
+    let (producer, consumer) = ring(latency * channels);
+    let renderer = Renderer {
+        playable,
+        swaps:    None,
+        next:     None,
+        cues:     None,
+        producer,
+        position: 0,
+        epoch:    0,
+        frame:    vec![0.0; channels],
+        status:   status.clone(),
+        rate:     SAMPLE_RATE,
+    };
+    let player = Player {
+        consumer,
+        channels,
+        latency,
+        scratch:  vec![0.0; MAX_CYCLE_FRAMES * channels],
+        frames:   0,
+        position: 0,
+        head:     0,
+        epoch:    0,
+        locating: false,
+        sampler:  None,
+        status,
+    };
+    (renderer, player)
+}
+
+/// Ways to steer playback from other threads while it is running.
+pub struct Controls<P> {
+    /// Shared playback state, which can also start, stop and locate.
+    pub status: Arc<Status>,
+    /// New versions of what is playing.
+    pub swaps:  Option<Receiver<Swap<P>>>,
+    /// Where to send messages to other programs.
+    pub cues:   Option<Sender<Cue>>,
+    /// Sources to play from their cue points on demand.
+    pub sampler: Option<Sampler>,
+}
+
+impl<P> Controls<P> {
+    /// Creates controls that start out rolling from the beginning.
+    pub fn new () -> Controls<P> {
+        Controls {
+            status:  Arc::new(Status::default()),
+            swaps:   None,
+            cues:    None,
+            sampler: None
+        }
+    }
+}
+
+/// Keeps `renderer` filling its ring buffer of `latency` frames
+/// on a worker thread named `name`, until `Status::end` is called.
+/// `rate` is the sample rate of the backend.
+pub fn spawn<P: Playable + 'static> (
+    renderer: Renderer<P>, latency: FrameTime, rate: usize, name: &str
+) -> JoinHandle<()> {
+    spawn_all(vec![renderer], latency, rate, name)
+}
+
+/// Keeps all `renderers` filling their ring buffers of `latency` frames
+/// on one worker thread named `name`, each until `Status::end` is called
+/// on it. `rate` is the sample rate of the backend.
+pub fn spawn_all<P: Playable + 'static> (
+    mut renderers: Vec<Renderer<P>>, latency: FrameTime, rate: usize, name: &str
+) -> JoinHandle<()> {
+    for renderer in renderers.iter_mut() {
+        renderer.rate = rate;
+        renderer.fill();
+    }
+    // wake up about four times per ring buffer's worth of frames
+    let rate = rate.max(1) as u64;
+    let nap = Duration::from_micros(latency as u64 * 250_000 / rate);
+
+    ThreadBuilder::new()
+        .name(name.into())
+        .spawn(move || {
+            loop {
+                renderers.retain(|renderer| !renderer.status.stopped());
+                if renderers.is_empty() { break }
+                let rendered: FrameTime = renderers.iter_mut()
+                    .map(|renderer| renderer.fill())
+                    .sum();
+                if rendered == 0 { sleep(nap) }
+            }
+        })
+        .unwrap_or_else(|e| panic!("Failed to start renderer: {:?}", e))
+}
+
+/// Plays `playable` through `backend`, rendering up to `latency` frames
+/// ahead on a worker thread, and steered through `controls`.
+/// Returns when the backend stops playing.
+pub fn play<P: Playable + 'static, B: Backend> (
+    playable: P,
+    backend:  &mut B,
+    latency:  FrameTime,
+    controls: Controls<P>
+) -> Arc<Status> {
+    let status = controls.status;
+    let (mut renderer, mut player) = prepare(playable, latency, status.clone());
+    if let Some(swaps) = controls.swaps { renderer.swap_from(swaps) }
+    if let Some(cues) = controls.cues { renderer.cue_to(cues) }
+    if let Some(mut sampler) = controls.sampler {
+        sampler.start(backend.sample_rate());
+        player.mix(sampler)
+    }
+
+    let worker = spawn(renderer, latency, backend.sample_rate(), "lude renderer");
+    backend.run(player);
+    status.end();
+    worker.join().unwrap_or_else(|_| panic!("Renderer panicked"));
+    eprintln!("stopped at frame {} after {} underruns",
+        status.position(), status.underruns());
+    status
+}
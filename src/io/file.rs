@@ -1,25 +1,203 @@
 /// read/write from/to files
+use std::collections::HashMap;
 use std::time::Instant;
-use crate::types::{Frame, Sample};
-use crate::media::sndfile::{SndFile, SndInfo, OpenMode, FormatType};
+use crate::document::Document;
+use crate::resource::hash;
+use crate::types::{Frame, FrameTime, Sample, SAMPLE_RATE};
+use crate::media::sndfile::{
+    SndFile, SndInfo, OpenMode, FormatType, CuePoint, StringSoundType, MAX_CUES
+};
 
-pub fn write_to_file (frames: Vec<Frame>, path: &str) {
+/// Source code longer than this is not embedded in a rendered file;
+/// only its hash is.
+pub const MAX_EMBEDDED_SOURCE: usize = 1 << 16;
+
+/// How a rendered file was made, kept in its metadata
+/// so that it can be made again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Provenance {
+    /// Version of Lude that rendered it.
+    pub version: String,
+    /// Frames per second.
+    pub rate:    usize,
+    /// Paths of the files that the document is made from.
+    pub sources: Vec<String>,
+    /// Hash of the source code, as per `resource::hash`.
+    pub hash:    u64,
+    /// The source code, unless it was longer than `MAX_EMBEDDED_SOURCE`.
+    pub source:  Option<String>,
+}
+
+impl Provenance {
+    /// Describes how `document` is rendered from `source`
+    /// by this version of Lude.
+    pub fn new (document: &Document, source: &str) -> Provenance {
+        Provenance {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            rate:    SAMPLE_RATE,
+            sources: document.resources.list()
+                .filter_map(|(_, resource)| resource.path.clone())
+                .collect(),
+            hash:    hash(source.as_bytes()),
+            source:  if source.len() <= MAX_EMBEDDED_SOURCE {
+                Some(source.to_string())
+            } else {
+                None
+            }
+        }
+    }
+    /// Writes it as a comment: one `lude-KEY: VALUE` line per setting,
+    /// then an empty line and the source code.
+    pub fn to_comment (&self) -> String {
+        let mut comment = format!(
+            "lude-version: {}\nlude-rate: {}\nlude-hash: {:016x}\n",
+            self.version, self.rate, self.hash
+        );
+        for path in self.sources.iter() {
+            comment.push_str(&format!("lude-source: {}\n", path));
+        }
+        if let Some(source) = &self.source {
+            comment.push('\n');
+            comment.push_str(source);
+        }
+        comment
+    }
+    /// Reads it back from a comment written by `to_comment`.
+    pub fn from_comment (comment: &str) -> Result<Provenance, String> {
+        let mut provenance = Provenance {
+            version: String::new(),
+            rate:    0,
+            sources: Vec::new(),
+            hash:    0,
+            source:  None
+        };
+        let (header, source) = match comment.find("\n\n") {
+            Some(end) => (&comment[..end], Some(&comment[end + 2..])),
+            None => (comment.trim_end_matches('\n'), None)
+        };
+        let mut hashed = false;
+        for line in header.lines() {
+            let mut parts = line.splitn(2, ": ");
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key, value),
+                _ => return Err(format!("not written by Lude: {}", line))
+            };
+            match key {
+                "lude-version" => provenance.version = value.to_string(),
+                "lude-rate" => provenance.rate = value.parse()
+                    .map_err(|_| format!("invalid rate: {}", value))?,
+                "lude-hash" => {
+                    provenance.hash = u64::from_str_radix(value, 16)
+                        .map_err(|_| format!("invalid hash: {}", value))?;
+                    hashed = true
+                },
+                "lude-source" => provenance.sources.push(value.to_string()),
+                _ => return Err(format!("not written by Lude: {}", line))
+            }
+        }
+        if !hashed { return Err(String::from("not written by Lude")) }
+        if let Some(source) = source {
+            if hash(source.as_bytes()) != provenance.hash {
+                return Err(String::from("the source code does not match its hash"))
+            }
+            provenance.source = Some(source.to_string());
+        }
+        Ok(provenance)
+    }
+}
+
+/// Reads how the file at `path` was rendered from its metadata.
+pub fn read_provenance (path: &str) -> Result<Provenance, String> {
+    let file = SndFile::new(path, OpenMode::Read)?;
+    let comment = file.get_string(StringSoundType::Comment);
+    file.close();
+    Provenance::from_comment(&comment.ok_or_else(|| String::from("no metadata"))?)
+}
+
+/// What `write_to_file` writes along with the frames.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WriteOptions {
+    /// Number of channels of every frame.
+    pub channels:   usize,
+    /// Where to mark cue points. Only the first `MAX_CUES` fit;
+    /// the rest are dropped, with a warning.
+    pub cues:       Vec<CuePoint>,
+    /// How the file was made, if known, kept in its metadata.
+    pub provenance: Option<Provenance>,
+}
+
+impl Default for WriteOptions {
+    fn default () -> WriteOptions {
+        WriteOptions { channels: 1, cues: Vec::new(), provenance: None }
+    }
+}
+
+/// Writes `frames` to a WAV file at `path`, as per `options`.
+pub fn write_to_file (frames: Vec<Frame>, path: &str, options: &WriteOptions) {
     let start = Instant::now();
+    let WriteOptions { channels, cues, provenance } = options;
     let mut flat_frames: Vec<Sample> = frames.into_iter().flatten().collect();
     let items = flat_frames.len() as i64;
     let mut sndfile = SndFile::new_with_info(
-        &path,
+        path,
         OpenMode::Write,
         Box::new(SndInfo {
-            frames: items,
+            frames: items / (*channels).max(1) as i64,
             samplerate: 44100,
-            channels: 1,
-            format: (FormatType::FormatWav|FormatType::FormatPcm16) as i32,
+            channels: *channels as i32,
+            format: FormatType::FormatWav as i32 | FormatType::FormatPcm16 as i32,
             sections: 0,
             seekable: 0
         })
     ).unwrap();
+    if cues.len() > MAX_CUES {
+        eprintln!("{} can only have {} cue points, dropping the last {}",
+            &path, MAX_CUES, cues.len() - MAX_CUES)
+    }
+    let cues = &cues[..cues.len().min(MAX_CUES)];
+    if !cues.is_empty() {
+        if let Err(e) = sndfile.set_cues(cues) { eprintln!("{}: {}", &path, e) }
+    }
+    if let Some(provenance) = provenance {
+        let software = format!("lude {}", provenance.version);
+        sndfile.set_string(StringSoundType::Software, software);
+        sndfile.set_string(StringSoundType::Comment, provenance.to_comment());
+    }
     sndfile.write_i16(flat_frames.as_mut_slice(), items);
+    sndfile.close();
     eprintln!("wrote {} samples to {} in {}usec ",
         &flat_frames.len(), &path, start.elapsed().as_micros());
 }
+
+/// Gets the cue points to write along with the rendered `document`:
+/// every marker, and if `events` is set, the start of every event,
+/// named after its source. Positions are in playback time, so a point
+/// inside a loop is marked once per repetition, and the name of every
+/// repetition after the first gets its number appended, e.g. `verse_2`.
+pub fn cue_points (document: &Document, events: bool) -> Vec<CuePoint> {
+    let mut points: Vec<(String, FrameTime)> = document.markers.iter()
+        .map(|(name, frame)| (name.clone(), *frame))
+        .collect();
+    if events {
+        for (frame, event) in document.events.iter() {
+            points.push((document.resources.get(event.src()).name.clone(), frame));
+        }
+    }
+    let mut points: Vec<(String, FrameTime)> = points.into_iter()
+        .flat_map(|(name, frame)| {
+            let positions = document.replay(frame).unwrap_or_else(|| vec![frame]);
+            positions.into_iter().map(move |position| (name.clone(), position))
+        })
+        .collect();
+    points.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+    let mut counts = HashMap::new();
+    points.into_iter().map(|(name, position)| {
+        let count = counts.entry(name.clone()).or_insert(0);
+        *count += 1;
+        let name = match *count {
+            1 => name,
+            count => format!("{}_{}", name, count)
+        };
+        CuePoint { name, position: position as i64 }
+    }).collect()
+}
@@ -1,8 +1,11 @@
-/// write frames to JACK
+//! write frames to JACK
 
-use std::sync::{Arc, Mutex};
-use std::sync::mpsc::channel;
-use crate::Document;
+use std::os::raw::{c_int, c_void};
+use std::thread::sleep;
+use std::time::Duration;
+use crate::document::Tempo;
+use crate::types::FrameTime;
+use super::engine::{Backend, Player};
 
 use jack::{
     AudioOut,
@@ -13,151 +16,136 @@ use jack::{
     ProcessScope,
 };
 
-pub fn start_jack_engine (mut document: Document) {
-
-    let (client, status) = Client::new("sequence", ClientOptions::empty())
-        .unwrap_or_else(|e| panic!("Failed to open JACK client: {:?}", e));
-    eprintln!("JACK client {}, status {:?}", client.name(), status);
-    let mut output = client.register_port("output", AudioOut::default())
-        .unwrap_or_else(|e| panic!("Failed to register output port: {:?}", e));
-
-    // create two channels for back-and-forth via main and jack threads
-    let (req_tx, req_rx) = channel();
-    let (res_tx, res_rx) = channel::<Arc<Vec<f32>>>();
-
-    // apparently, the ends of the channels that are moved into
-    // the closure that runs on the jack callback thread
-    // need to be wrapped with some sort of sync primitive
-    // all I know is Arc<Mutex<T>>, let's hope it's good enough
-    let req = Arc::new(Mutex::new(req_tx));
-    let res = Arc::new(Mutex::new(res_rx));
-    let async_client = client.activate_async(
-        (),
-        ClosureProcessHandler::new(move |_: &Client, scope: &ProcessScope| {
-            // jack handler requests data based on current time
-            {
-                req.lock().unwrap().send((
-                    scope.last_frame_time(),
-                    scope.n_frames(),
-                    scope.frames_since_cycle_start()
-                ));
-            }
-            // and waits for main engine loop to responds with
-            // rendered data that should be output this  frame
-            {
-                let arc = res.lock().unwrap().recv().unwrap();
-                let data = Arc::try_unwrap(arc)
-                    .unwrap_or_else(|_| panic!("Failed to read response data"));
-                let mut output_data = output.as_mut_slice(scope);
-                for i in 0..1024 {
-                    output_data[i] = data[i];
-                }
-                //output.as_mut_slice(scope).swap_with_slice(data.as_mut_slice());
-            }
-            Control::Continue
-        })
-    ).unwrap_or_else(|e| panic!("Failed to active JACK client: {:?}", e));
+use jack_sys::{
+    jack_nframes_t,
+    jack_position_t,
+    jack_transport_state_t,
+    jack_transport_query,
+    jack_set_timebase_callback,
+    jack_release_timebase,
+    JackTransportRolling,
+    JackPositionBBT,
+};
 
-    eprintln!("client active: {:#?}", &async_client);
+/// Plays through a JACK client with one output port per channel.
+pub struct JackBackend {
+    client: Option<Client>,
+    /// If set, follow JACK transport instead of playing from the start.
+    pub transport: bool,
+    /// If set, keep running after the end of the document.
+    pub hold: bool,
+    /// If set, publish bars and beats as the JACK timebase master.
+    pub timebase: Option<Tempo>,
+}
 
-    // main loop: waits for data to be requested.
-    // generates it based on timing from jack thread,
-    // sends it to be used to populate the output buffer.
-    // Arc<T> is being used so that the data is not dropped
-    // not cool: allocation on every cycle
-    loop {
-        let (start, size, skip) = req_rx.recv().unwrap();
-        let mut output_data = Vec::with_capacity(size as usize);
-        for i in 0..1024 {
-            output_data.push(i as f32 / 1024 as f32);
+impl JackBackend {
+    /// Opens a JACK client.
+    pub fn new () -> JackBackend {
+        let (client, status) = Client::new("lude", ClientOptions::empty())
+            .unwrap_or_else(|e| panic!("Failed to open JACK client: {:?}", e));
+        eprintln!("JACK client {}, status {:?}", client.name(), status);
+        JackBackend {
+            client:    Some(client),
+            transport: false,
+            hold:      false,
+            timebase:  None
         }
-        //eprintln!("tick {} {} {}", start, size, skip);
-        let arc = Arc::new(output_data);
-        res_tx.send(arc);
     }
-
 }
 
-/*
-
-pub struct Engine {
-    output: Port<AudioOut>
-}
-
-impl NotificationHandler for Engine {
-
-    fn thread_init (&self, _client: &Client) {}
-
-    fn shutdown (&mut self, _status: ClientStatus, _reason: &str) {}
-
-    fn freewheel (&mut self, _client: &Client, _enabled: bool) {}
-
-    fn buffer_size (&mut self, _client: &Client, _size: Frames) -> Control {
-        Control::Continue
-    }
-
-    fn sample_rate (&mut self, _client: &Client, _rate: Frames) -> Control {
-        Control::Continue
-    }
-
-    fn port_registration (
-        &mut self, _client: &Client, _id: PortId, _reg: bool
-    ) {}
-
-    fn port_rename (
-        &mut self, _client: &Client, _id: PortId, _old: &str, _new: &str
-    ) -> Control {
-        Control::Continue
+impl Backend for JackBackend {
+    fn sample_rate (&self) -> usize {
+        self.client.as_ref().map_or(0, |client| client.sample_rate())
     }
+    fn run (&mut self, mut player: Player) {
+        let client = self.client.take()
+            .unwrap_or_else(|| panic!("JACK client is already running"));
+
+        let channels = player.channels();
+        let mut outputs = Vec::with_capacity(channels);
+        for channel in 0..channels {
+            let name = format!("output_{}", channel + 1);
+            outputs.push(client.register_port(&name, AudioOut)
+                .unwrap_or_else(|e| panic!("Failed to register {}: {:?}", name, e)));
+        }
 
-    fn ports_connected (
-        &mut self, _client: &Client, _id1: PortId, _id2: PortId, _conn: bool
-    ) {}
-
-    fn graph_reorder (&mut self, _client: &Client) -> Control {
-        Control::Continue
-    }
+        // the process callback runs on the jack thread and only
+        // copies what the renderer has already put in the ring buffer
+        let transport = self.transport;
+        let status = player.status().clone();
+        let async_client = client.activate_async(
+            (),
+            ClosureProcessHandler::new(move |_: &Client, scope: &ProcessScope| {
+                let n_frames = scope.n_frames() as FrameTime;
+                if transport {
+                    let mut position = jack_position_t::default();
+                    let state = unsafe {
+                        jack_transport_query(scope.client_ptr(), &mut position)
+                    };
+                    let frame = position.frame as FrameTime;
+                    let rolling = state == JackTransportRolling;
+                    player.process_at(frame, n_frames, rolling);
+                } else {
+                    player.process(n_frames);
+                }
+                for (channel, output) in outputs.iter_mut().enumerate() {
+                    player.write_channel(channel, output.as_mut_slice(scope));
+                }
+                Control::Continue
+            })
+        ).unwrap_or_else(|e| panic!("Failed to active JACK client: {:?}", e));
+
+        eprintln!("client active: {:#?}", &async_client);
+
+        // the tempo is boxed so that it stays put while jack points to it
+        let raw = async_client.as_client().raw();
+        let timebase = self.timebase.map(|tempo| {
+            let tempo = Box::into_raw(Box::new(tempo));
+            let result = unsafe {
+                jack_set_timebase_callback(raw, 0, Some(publish_bbt), tempo as *mut c_void)
+            };
+            if result != 0 { eprintln!("Failed to become timebase master") }
+            tempo
+        });
+
+        while self.hold || !status.finished() {
+            sleep(Duration::from_millis(10));
+        }
 
-    fn xrun (&mut self, _client: &Client) -> Control {
-        Control::Continue
+        if let Some(tempo) = timebase {
+            unsafe {
+                jack_release_timebase(raw);
+                drop(Box::from_raw(tempo));
+            }
+        }
+        let (client, _, _) = async_client.deactivate()
+            .unwrap_or_else(|e| panic!("Failed to deactivate JACK client: {:?}", e));
+        self.client = Some(client);
     }
-
-    fn latency (&mut self, _client: &Client, _mode: LatencyType) {}
-
 }
 
-impl ProcessHandler for Engine {
-    fn process (
-        &mut self,
-        _client: &Client,
-        process_scope: &ProcessScope
-    ) -> Control{
-        Control::Continue
-    }
+/// Called by JACK once per cycle while lude is the timebase master,
+/// to fill in the bar, beat and tempo for the current transport frame.
+unsafe extern "C" fn publish_bbt (
+    _state:   jack_transport_state_t,
+    _nframes: jack_nframes_t,
+    position: *mut jack_position_t,
+    _new_pos: c_int,
+    tempo:    *mut c_void
+) {
+    let tempo = &*(tempo as *const Tempo);
+    let rate = (*position).frame_rate as usize;
+    let frame = (*position).frame as FrameTime;
+    let (bar, beat, tick) = tempo.bbt(frame, rate);
+    let ticks_per_beat = Tempo::TICKS_PER_BEAT;
+    let beats_per_bar = tempo.beats_per_bar as f64;
+    (*position).valid |= JackPositionBBT;
+    (*position).bar = bar as i32;
+    (*position).beat = beat as i32;
+    (*position).tick = tick as i32;
+    (*position).bar_start_tick = (bar - 1) as f64 * beats_per_bar * ticks_per_beat;
+    (*position).beats_per_bar = beats_per_bar as f32;
+    (*position).beat_type = 4.0;
+    (*position).ticks_per_beat = ticks_per_beat;
+    (*position).beats_per_minute = tempo.bpm;
 }
-
-*/
-
-/*
-
-    let mut current_frames = 0;
-    let mut current_usecs = 0;
-    let mut next_usecs = 0;
-    let mut period_usecs = 0.0;
-*/
-
-            /*
-            let cycle_times = scope.cycle_times().unwrap();
-            eprintln!("cycle {:#?}", &cycle_times);
-            eprintln!("d current_frames {}", cycle_times.current_frames - current_frames);
-            current_frames = cycle_times.current_frames;
-            eprintln!("d current_usecs {}", cycle_times.current_usecs - current_usecs);
-            current_usecs = cycle_times.current_usecs;
-            eprintln!("d next_usecs {}", cycle_times.next_usecs - next_usecs);
-            next_usecs = cycle_times.next_usecs;
-            eprintln!("d period_usecs {}", cycle_times.period_usecs - period_usecs);
-            period_usecs = cycle_times.period_usecs;
-
-            let d_usecs = next_usecs - current_usecs;
-            eprintln!("d usecs {}", d_usecs);*/
-
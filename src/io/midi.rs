@@ -0,0 +1,339 @@
+//! convert documents to Standard MIDI Files, and back to source code
+//!
+//! Each source becomes a track, and each event a note which starts
+//! and ends with the event. Which note stands for which source is
+//! decided by a `NoteMap`, which can be read from a mapping file
+//! with one `NOTE SOURCE [SLICE]` line per note, e.g. `36 ./data/kick.wav`
+//! or `38 ./data/break.wav |4410:8820|`. The slice is only used
+//! when importing, and defaults to the whole source.
+
+use std::collections::HashMap;
+use crate::document::{Document, Tempo};
+use crate::types::{FrameTime, SAMPLE_RATE};
+
+/// Resolution of the exported files.
+pub const TICKS_PER_QUARTER: u16 = 480;
+
+/// Channel of the notes: channel 10, for General MIDI percussion.
+pub const MIDI_CHANNEL: u8 = 9;
+
+/// Velocity of the notes.
+pub const VELOCITY: u8 = 100;
+
+/// The note given to the first source that is not in the mapping:
+/// C1, the bass drum in General MIDI percussion.
+pub const FIRST_NOTE: u8 = 36;
+
+/// A note being switched on or off: tick, whether on, note.
+type NoteEvent = (u32, bool, u8);
+
+/// Which note stands for which source.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct NoteMap {
+    /// Pairs of note and source path, in the order they were added.
+    pub notes:  Vec<(u8, String)>,
+    /// What slice of the source each note is imported as, if not `||`.
+    pub slices: HashMap<u8, String>,
+}
+
+impl NoteMap {
+    /// Reads a mapping file with one `NOTE SOURCE [SLICE]` line per note.
+    /// Empty lines and lines starting with `(` are ignored.
+    pub fn parse (text: &str) -> Result<NoteMap, String> {
+        let mut map = NoteMap::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('(') { continue }
+            let mut fields = line.split_whitespace();
+            let note = fields.next().unwrap();
+            let note = note.parse::<u8>().ok().filter(|note| *note < 128)
+                .ok_or_else(|| format!("line {}: invalid note {}", number + 1, note))?;
+            let src = fields.next()
+                .ok_or_else(|| format!("line {}: missing source", number + 1))?;
+            map.notes.push((note, src.to_string()));
+            let slice = fields.collect::<Vec<_>>().join(" ");
+            if !slice.is_empty() { map.slices.insert(note, slice); }
+        }
+        Ok(map)
+    }
+    /// Gets the note for `src`.
+    pub fn note (&self, src: &str) -> Option<u8> {
+        self.notes.iter().find(|(_, s)| s == src).map(|(note, _)| *note)
+    }
+    /// Gets the source for `note`.
+    pub fn src (&self, note: u8) -> Option<&str> {
+        self.notes.iter().find(|(n, _)| *n == note).map(|(_, src)| src.as_str())
+    }
+    /// Gets the note for `src`, giving it the next unused note
+    /// from `FIRST_NOTE` up if it does not have one yet.
+    pub fn note_or_insert (&mut self, src: &str) -> Result<u8, String> {
+        if let Some(note) = self.note(src) { return Ok(note) }
+        let note = (FIRST_NOTE..128).find(|note| self.src(*note).is_none())
+            .ok_or_else(|| String::from("ran out of notes"))?;
+        self.notes.push((note, src.to_string()));
+        Ok(note)
+    }
+}
+
+/// Converts `document` to a format 1 Standard MIDI File, with the tempo
+/// and meter in the first track, followed by one track per source.
+/// Loops are written out in full, so endless loops can not be exported.
+pub fn export (document: &Document, notes: &NoteMap) -> Result<Vec<u8>, String> {
+    let tempo = document.tempo.unwrap_or_default();
+    let mut notes = notes.clone();
+    let mut tracks: Vec<(String, Vec<NoteEvent>)> = Vec::new();
+    for (start, event) in document.events.iter() {
+        let path = document.resources.path(event.src());
+        let note = notes.note_or_insert(path)?;
+        let index = match tracks.iter().position(|(src, _)| src == path) {
+            Some(index) => index,
+            None => {
+                tracks.push((path.to_string(), Vec::new()));
+                tracks.len() - 1
+            }
+        };
+        let starts = document.replay(start)
+            .ok_or_else(|| String::from("can not export an endless loop"))?;
+        for start in starts {
+            let on = frames_to_ticks(start, &tempo);
+            let off = frames_to_ticks(start + event.len(), &tempo).max(on + 1);
+            tracks[index].1.push((on, true, note));
+            tracks[index].1.push((off, false, note));
+        }
+    }
+
+    let mut file = Vec::new();
+    file.extend_from_slice(b"MThd");
+    file.extend_from_slice(&6u32.to_be_bytes());
+    file.extend_from_slice(&1u16.to_be_bytes());
+    file.extend_from_slice(&(tracks.len() as u16 + 1).to_be_bytes());
+    file.extend_from_slice(&TICKS_PER_QUARTER.to_be_bytes());
+
+    let mut track = Track::default();
+    let micros = (60_000_000.0 / tempo.bpm).round() as u32;
+    track.event(0, &[0xFF, 0x51, 0x03]);
+    track.data.extend_from_slice(&micros.to_be_bytes()[1..]);
+    track.event(0, &[0xFF, 0x58, 0x04, tempo.beats_per_bar as u8, 2, 24, 8]);
+    track.write(&mut file);
+
+    for (src, mut notes) in tracks {
+        let mut track = Track::default();
+        track.event(0, &[0xFF, 0x03]);
+        write_var_len(&mut track.data, src.len() as u32);
+        track.data.extend_from_slice(src.as_bytes());
+        // at the same tick, end the previous note before starting the next
+        notes.sort_by_key(|(tick, on, _)| (*tick, *on));
+        for (tick, on, note) in notes {
+            let status = if on { 0x90 } else { 0x80 } | MIDI_CHANNEL;
+            track.event(tick, &[status, note, if on { VELOCITY } else { 0 }]);
+        }
+        track.write(&mut file);
+    }
+    Ok(file)
+}
+
+/// Converts a number of frames to ticks at `tempo`.
+pub fn frames_to_ticks (frames: FrameTime, tempo: &Tempo) -> u32 {
+    let beat = tempo.beat_frames(SAMPLE_RATE);
+    (frames as f64 * TICKS_PER_QUARTER as f64 / beat).round() as u32
+}
+
+/// A note read from a MIDI file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Note {
+    tick: u32,
+    note: u8,
+}
+
+/// Converts a Standard MIDI File to Lude source code, in which
+/// each note that is in `notes` is written as a slice of its source,
+/// at the same point in time. Other notes are skipped.
+pub fn import (midi: &[u8], notes: &NoteMap) -> Result<String, String> {
+    let (division, tracks) = read_chunks(midi)?;
+    let mut tempos = Vec::new();
+    let mut meter = None;
+    let mut played = Vec::new();
+    for track in tracks.iter() {
+        read_track(track, &mut played, &mut tempos, &mut meter)?;
+    }
+    tempos.sort_by_key(|(tick, _)| *tick);
+    played.sort_by_key(|note| (note.tick, note.note));
+    let frame_at = |tick: u32| ticks_to_frames(tick, division, &tempos);
+
+    let mut source = String::new();
+    let micros = tempos.first().map(|(_, micros)| *micros).unwrap_or(500_000);
+    let bpm = 60_000_000.0 / micros as f64;
+    source.push_str(&format!("!bpm {} !meter {}\n", bpm.round(), meter.unwrap_or(4)));
+    let mut active = None;
+    let mut skipped = Vec::new();
+    for note in played.iter() {
+        let src = match notes.src(note.note) {
+            Some(src) => src,
+            None => {
+                if !skipped.contains(&note.note) {
+                    eprintln!("note {} is not mapped, skipping", note.note);
+                    skipped.push(note.note);
+                }
+                continue
+            }
+        };
+        source.push_str(&format!("@{} ", frame_at(note.tick)));
+        if active != Some(src) {
+            source.push_str(src);
+            source.push(' ');
+            active = Some(src);
+        }
+        match notes.slices.get(&note.note) {
+            Some(slice) => source.push_str(slice),
+            None => source.push_str("||")
+        }
+        source.push('\n');
+    }
+    Ok(source)
+}
+
+/// Converts ticks to frames, following the tempo changes in `tempos`,
+/// which are pairs of tick and microseconds per quarter note.
+fn ticks_to_frames (tick: u32, division: u16, tempos: &[(u32, u32)]) -> FrameTime {
+    let mut micros = 0.0;
+    let mut last = (0, 500_000);
+    for (at, tempo) in tempos.iter().take_while(|(at, _)| *at < tick) {
+        micros += (at - last.0) as f64 * last.1 as f64;
+        last = (*at, *tempo);
+    }
+    micros += (tick - last.0) as f64 * last.1 as f64;
+    (micros / division as f64 * SAMPLE_RATE as f64 / 1_000_000.0).round() as FrameTime
+}
+
+/// Gets the division (ticks per quarter note) and the track chunks.
+fn read_chunks (midi: &[u8]) -> Result<(u16, Vec<&[u8]>), String> {
+    let mut offset = 0;
+    let mut division = None;
+    let mut tracks = Vec::new();
+    while offset + 8 <= midi.len() {
+        let kind = &midi[offset..offset + 4];
+        let len = u32::from_be_bytes([
+            midi[offset + 4], midi[offset + 5], midi[offset + 6], midi[offset + 7]
+        ]) as usize;
+        let data = midi.get(offset + 8..offset + 8 + len)
+            .ok_or_else(|| String::from("truncated chunk"))?;
+        match kind {
+            b"MThd" if data.len() >= 6 => {
+                let value = u16::from_be_bytes([data[4], data[5]]);
+                if value & 0x8000 != 0 {
+                    return Err(String::from("SMPTE time is not supported"))
+                }
+                division = Some(value)
+            },
+            b"MTrk" => tracks.push(data),
+            _ => {}
+        }
+        offset += 8 + len;
+    }
+    let division = division.ok_or_else(|| String::from("not a MIDI file"))?;
+    Ok((division.max(1), tracks))
+}
+
+/// Reads the note ons, tempo changes and first meter of a track chunk.
+fn read_track (
+    data:   &[u8],
+    played: &mut Vec<Note>,
+    tempos: &mut Vec<(u32, u32)>,
+    meter:  &mut Option<u8>
+) -> Result<(), String> {
+    let truncated = || String::from("truncated track");
+    let mut offset = 0;
+    let mut tick: u32 = 0;
+    let mut running = 0;
+    while offset < data.len() {
+        let delta = read_var_len(data, &mut offset).ok_or_else(truncated)?;
+        tick = tick.checked_add(delta).ok_or_else(truncated)?;
+        let mut status = *data.get(offset).ok_or_else(truncated)?;
+        if status < 0x80 {
+            // running status: the previous status byte is reused
+            status = running;
+        } else {
+            offset += 1;
+        }
+        match status {
+            0xFF => {
+                let kind = *data.get(offset).ok_or_else(truncated)?;
+                offset += 1;
+                let len = read_var_len(data, &mut offset).ok_or_else(truncated)? as usize;
+                let value = data.get(offset..offset + len).ok_or_else(truncated)?;
+                offset += len;
+                match kind {
+                    0x51 if len == 3 => tempos.push((tick, u32::from_be_bytes(
+                        [0, value[0], value[1], value[2]]
+                    ))),
+                    0x58 if len >= 1 => { meter.get_or_insert(value[0]); },
+                    0x2F => break,
+                    _ => {}
+                }
+            },
+            0xF0 | 0xF7 => {
+                let len = read_var_len(data, &mut offset).ok_or_else(truncated)? as usize;
+                offset += len;
+            },
+            0x80..=0xEF => {
+                running = status;
+                let size = match status & 0xF0 { 0xC0 | 0xD0 => 1, _ => 2 };
+                let bytes = data.get(offset..offset + size).ok_or_else(truncated)?;
+                offset += size;
+                // note on with a velocity of 0 is a note off
+                if status & 0xF0 == 0x90 && bytes[1] > 0 {
+                    played.push(Note { tick, note: bytes[0] });
+                }
+            },
+            _ => return Err(format!("unexpected status byte {:#x}", status))
+        }
+    }
+    Ok(())
+}
+
+/// Reads a variable-length quantity, which is at most 4 bytes long.
+fn read_var_len (data: &[u8], offset: &mut usize) -> Option<u32> {
+    let mut value: u32 = 0;
+    for _ in 0..4 {
+        let byte = *data.get(*offset)?;
+        *offset += 1;
+        value = (value << 7) | (byte & 0x7F) as u32;
+        if byte & 0x80 == 0 { return Some(value) }
+    }
+    None
+}
+
+/// The events of a track, with delta times.
+#[derive(Default)]
+struct Track {
+    data: Vec<u8>,
+    tick: u32,
+}
+
+impl Track {
+    fn event (&mut self, tick: u32, bytes: &[u8]) {
+        write_var_len(&mut self.data, tick - self.tick);
+        self.tick = tick;
+        self.data.extend_from_slice(bytes);
+    }
+    fn write (mut self, file: &mut Vec<u8>) {
+        let tick = self.tick;
+        self.event(tick, &[0xFF, 0x2F, 0x00]);
+        file.extend_from_slice(b"MTrk");
+        file.extend_from_slice(&(self.data.len() as u32).to_be_bytes());
+        file.extend_from_slice(&self.data);
+    }
+}
+
+/// Writes a variable-length quantity: 7 bits per byte, most significant
+/// first, with the high bit set on all bytes but the last.
+fn write_var_len (data: &mut Vec<u8>, value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.reverse();
+    data.extend_from_slice(&bytes);
+}
@@ -1 +1,10 @@
 pub mod file;
+pub mod ring;
+pub mod engine;
+pub mod dummy;
+pub mod watch;
+pub mod osc;
+pub mod udp_osc;
+pub mod midi;
+pub mod sequence;
+pub mod jack;
@@ -0,0 +1,130 @@
+//! encode and decode Open Sound Control messages
+//!
+//! Only what Lude needs from [OSC 1.0](http://opensoundcontrol.org/spec-1_0):
+//! single messages (no bundles) with `i`, `f` and `s` arguments.
+
+use crate::timeline::Span;
+
+/// An argument of an OSC message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArg {
+    /// 32-bit integer, type tag `i`.
+    Int(i32),
+    /// 32-bit float, type tag `f`.
+    Float(f32),
+    /// String, type tag `s`.
+    Str(String),
+}
+
+impl OscArg {
+    /// Gets the argument as a number, if it is one.
+    pub fn number (&self) -> Option<f64> {
+        match self {
+            OscArg::Int(value)   => Some(*value as f64),
+            OscArg::Float(value) => Some(*value as f64),
+            OscArg::Str(_)       => None
+        }
+    }
+    /// Gets the argument as a string, if it is one.
+    pub fn string (&self) -> Option<&str> {
+        match self {
+            OscArg::Str(value) => Some(value),
+            _ => None
+        }
+    }
+}
+
+/// A message sent to, or received from, an address pattern
+/// such as `/lude/start`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    /// Where the message goes.
+    pub addr: String,
+    /// What comes with it.
+    pub args: Vec<OscArg>,
+}
+
+/// Messages take no time.
+impl Span for OscMessage {}
+
+impl OscMessage {
+    /// Creates a message to `addr` with `args`.
+    pub fn new (addr: &str, args: Vec<OscArg>) -> OscMessage {
+        OscMessage { addr: addr.to_string(), args }
+    }
+    /// Encodes the message into the bytes of a packet.
+    pub fn encode (&self) -> Vec<u8> {
+        let mut packet = Vec::new();
+        write_string(&mut packet, &self.addr);
+        let mut tags = String::from(",");
+        for arg in self.args.iter() {
+            tags.push(match arg {
+                OscArg::Int(_)   => 'i',
+                OscArg::Float(_) => 'f',
+                OscArg::Str(_)   => 's'
+            })
+        }
+        write_string(&mut packet, &tags);
+        for arg in self.args.iter() {
+            match arg {
+                OscArg::Int(value)   => packet.extend_from_slice(&value.to_be_bytes()),
+                OscArg::Float(value) => packet.extend_from_slice(&value.to_bits().to_be_bytes()),
+                OscArg::Str(value)   => write_string(&mut packet, value)
+            }
+        }
+        packet
+    }
+    /// Decodes a message from the bytes of a packet.
+    pub fn decode (packet: &[u8]) -> Result<OscMessage, String> {
+        let mut offset = 0;
+        let addr = read_string(packet, &mut offset)?;
+        if !addr.starts_with('/') {
+            return Err(format!("not an OSC message: {:?}", addr))
+        }
+        // the type tag string may be missing in old implementations
+        let tags = if offset < packet.len() {
+            read_string(packet, &mut offset)?
+        } else {
+            String::from(",")
+        };
+        if !tags.starts_with(',') {
+            return Err(format!("bad type tags: {:?}", tags))
+        }
+        let mut args = Vec::new();
+        for tag in tags.chars().skip(1) {
+            args.push(match tag {
+                'i' => OscArg::Int(i32::from_be_bytes(read_word(packet, &mut offset)?)),
+                'f' => OscArg::Float(f32::from_bits(
+                    u32::from_be_bytes(read_word(packet, &mut offset)?)
+                )),
+                's' => OscArg::Str(read_string(packet, &mut offset)?),
+                _ => return Err(format!("unsupported type tag: {}", tag))
+            })
+        }
+        Ok(OscMessage { addr, args })
+    }
+}
+
+/// Writes a null-terminated string, padded to a multiple of 4 bytes.
+fn write_string (packet: &mut Vec<u8>, value: &str) {
+    packet.extend_from_slice(value.as_bytes());
+    let padding = 4 - value.len() % 4;
+    packet.resize(packet.len() + padding, 0);
+}
+
+fn read_string (packet: &[u8], offset: &mut usize) -> Result<String, String> {
+    let rest = packet.get(*offset..).unwrap_or(&[]);
+    let len = rest.iter().position(|byte| *byte == 0)
+        .ok_or_else(|| String::from("unterminated string"))?;
+    let value = String::from_utf8(rest[..len].to_vec())
+        .map_err(|e| e.to_string())?;
+    *offset += (len / 4 + 1) * 4;
+    Ok(value)
+}
+
+fn read_word (packet: &[u8], offset: &mut usize) -> Result<[u8; 4], String> {
+    let bytes = packet.get(*offset..*offset + 4)
+        .ok_or_else(|| String::from("message too short"))?;
+    *offset += 4;
+    Ok([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
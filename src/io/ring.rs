@@ -0,0 +1,107 @@
+//! lock-free single-producer, single-consumer ring buffer of samples
+//!
+//! Samples are stored as the bits of `f32`s in atomics, so neither end
+//! needs locks or `unsafe` code. The read and write counters only ever
+//! increase; their difference is the number of samples in the buffer.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+#[derive(Debug)]
+struct Ring {
+    data:  Box<[AtomicU32]>,
+    read:  AtomicUsize,
+    write: AtomicUsize,
+}
+
+/// The writing end of a ring buffer.
+#[derive(Debug)]
+pub struct Producer {
+    ring: Arc<Ring>
+}
+
+/// The reading end of a ring buffer.
+#[derive(Debug)]
+pub struct Consumer {
+    ring: Arc<Ring>
+}
+
+/// Allocates a ring buffer which holds up to `capacity` samples.
+pub fn ring (capacity: usize) -> (Producer, Consumer) {
+    let data = (0..capacity.max(1)).map(|_| AtomicU32::new(0)).collect();
+    let ring = Arc::new(Ring {
+        data,
+        read:  AtomicUsize::new(0),
+        write: AtomicUsize::new(0)
+    });
+    (Producer { ring: ring.clone() }, Consumer { ring })
+}
+
+impl Ring {
+    fn capacity (&self) -> usize {
+        self.data.len()
+    }
+    fn len (&self) -> usize {
+        let write = self.write.load(Ordering::Acquire);
+        let read  = self.read.load(Ordering::Acquire);
+        write.wrapping_sub(read)
+    }
+}
+
+impl Producer {
+    /// Gets the number of samples that can be pushed.
+    pub fn free (&self) -> usize {
+        self.ring.capacity() - self.ring.len()
+    }
+    /// Discards everything that has not been popped yet.
+    /// The consumer must not be popping while this happens.
+    pub fn clear (&mut self) {
+        let read = self.ring.read.load(Ordering::Acquire);
+        self.ring.write.store(read, Ordering::Release);
+    }
+    /// Pushes as many `samples` as fit, all at once.
+    /// Returns the number of samples pushed.
+    pub fn push (&mut self, samples: &[f32]) -> usize {
+        let count = samples.len().min(self.free());
+        let write = self.ring.write.load(Ordering::Relaxed);
+        let capacity = self.ring.capacity();
+        for (i, sample) in samples.iter().take(count).enumerate() {
+            let index = write.wrapping_add(i) % capacity;
+            self.ring.data[index].store(sample.to_bits(), Ordering::Relaxed);
+        }
+        self.ring.write.store(write.wrapping_add(count), Ordering::Release);
+        count
+    }
+}
+
+impl Consumer {
+    /// Gets the number of samples that can be popped.
+    pub fn len (&self) -> usize {
+        self.ring.len()
+    }
+    /// Returns `true` if there is nothing to pop.
+    pub fn is_empty (&self) -> bool {
+        self.len() == 0
+    }
+    /// Pops as many samples as are available into `samples`.
+    /// Returns the number of samples popped.
+    pub fn pop (&mut self, samples: &mut [f32]) -> usize {
+        let count = samples.len().min(self.len());
+        let read = self.ring.read.load(Ordering::Relaxed);
+        let capacity = self.ring.capacity();
+        for (i, sample) in samples.iter_mut().take(count).enumerate() {
+            let index = read.wrapping_add(i) % capacity;
+            *sample = f32::from_bits(self.ring.data[index].load(Ordering::Relaxed));
+        }
+        self.ring.read.store(read.wrapping_add(count), Ordering::Release);
+        count
+    }
+    /// Discards up to `count` samples.
+    /// Returns the number of samples discarded.
+    pub fn skip (&mut self, count: usize) -> usize {
+        let count = count.min(self.len());
+        let read = self.ring.read.load(Ordering::Relaxed);
+        self.ring.read.store(read.wrapping_add(count), Ordering::Release);
+        count
+    }
+}
@@ -1,5 +1,28 @@
-use crate::{Timeline, resource::Identifier};
+//! non-audio event streams, sent out as playback reaches them
 
-pub type Sequence = Timeline<Identifier>;
+use crate::timeline::{Moment, Span, Timeline};
 
-pub struct Sequencer {}
+/// Events that happen at points in time but make no sound,
+/// such as messages to other programs.
+pub type Sequence<E> = Timeline<E>;
+
+/// Holds on to scheduled events until they are due.
+#[derive(Debug, Default)]
+pub struct Sequencer<E: Span> {
+    sequence: Sequence<E>,
+}
+
+impl<E: Span> Sequencer<E> {
+    /// Creates a sequencer with nothing scheduled.
+    pub fn new () -> Sequencer<E> {
+        Sequencer { sequence: Sequence::new() }
+    }
+    /// Schedules `event` to be due at `moment`.
+    pub fn schedule (&mut self, moment: Moment, event: E) {
+        self.sequence.add(moment, event)
+    }
+    /// Takes the events that are due before `now`, in order.
+    pub fn due (&mut self, now: Moment) -> Vec<(Moment, E)> {
+        self.sequence.drain_before(now)
+    }
+}
@@ -0,0 +1,263 @@
+//! control playback with OSC messages over UDP,
+//! and send the messages of a document to other programs
+//!
+//! Every message is answered, to the address it came from, with either
+//! `/lude/status position rolling underruns finished` or `/lude/error reason`.
+//!
+//! * `/lude/start` and `/lude/stop` start and stop playback.
+//! * `/lude/locate FRAME` continues playback from `FRAME`.
+//! * `/lude/marker NAME` continues playback from the marker `NAME`.
+//! * `/lude/mute SOURCE [0|1]` and `/lude/solo SOURCE [0|1]` mute
+//!   or solo a source (on by default, `0` turns it off again).
+//! * `/lude/reload` reads the source file again.
+//! * `/lude/set NAME NUMBER` overrides a setting, like `!NAME NUMBER` would.
+//!   Settings that only change how events are written, like `snap`,
+//!   can not be overridden.
+//! * `/lude/trigger SOURCE [CUE]` plays a source from its cue point `CUE`,
+//!   or from its start, on top of the document, if it was loaded into the
+//!   sampler (by name, as listed by `lude resources`).
+//! * `/lude/status` only asks for the status.
+//!
+//! Mute, solo and settings are applied to the last version of the document
+//! that was loaded, without reading the source file again, and are kept
+//! across reloads.
+
+use std::collections::HashSet;
+use std::net::UdpSocket;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Sender, TryRecvError};
+use std::thread::{Builder as ThreadBuilder, sleep};
+use std::time::Duration;
+use crate::document::Document;
+use crate::media::sampler::Triggers;
+use crate::types::FrameTime;
+use super::engine::{Cue, Quantize, Status, Swap};
+use super::osc::{OscArg, OscMessage};
+use super::sequence::Sequencer;
+
+/// How often the player's position is checked while waiting
+/// for a message to become due.
+pub const EMIT_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Changes to a document that were made during playback,
+/// and are applied again whenever it is reloaded.
+#[derive(Debug, Default)]
+pub struct Overrides {
+    /// Sources that are not heard.
+    pub muted:    HashSet<String>,
+    /// Sources that are heard, if any; all others are not.
+    pub soloed:   HashSet<String>,
+    /// Settings, in the order they were made.
+    pub settings: Vec<(String, f64)>,
+}
+
+impl Overrides {
+    /// Applies the overrides to `document`.
+    pub fn apply (&self, document: &mut Document) {
+        document.mute(self.muted.clone(), self.soloed.clone());
+        for (name, value) in self.settings.iter() {
+            if let Err(e) = document.set(name, *value) { eprintln!("{}", e) }
+        }
+    }
+}
+
+/// Handles control messages for a playing document.
+pub struct Server {
+    status:    Arc<Status>,
+    swaps:     Sender<Swap<Document>>,
+    overrides: Arc<Mutex<Overrides>>,
+    load:      Box<dyn Fn() -> Document + Send>,
+    latest:    Arc<Mutex<Document>>,
+    triggers:  Option<Triggers>,
+}
+
+impl Server {
+    /// Creates a server which steers playback through `status`.
+    /// Whenever the document needs to change, it calls `load`,
+    /// which is expected to apply `overrides` and to keep `latest`, the
+    /// last version that was loaded, up to date, and sends the result
+    /// through `swaps`. Mute, solo and settings change a copy of `latest`.
+    pub fn new<F> (
+        status:    Arc<Status>,
+        swaps:     Sender<Swap<Document>>,
+        overrides: Arc<Mutex<Overrides>>,
+        load:      F,
+        latest:    Arc<Mutex<Document>>
+    ) -> Server
+    where F: Fn() -> Document + Send + 'static {
+        Server { status, swaps, overrides, load: Box::new(load), latest, triggers: None }
+    }
+    /// Starts passing `/lude/trigger` messages on to `triggers`.
+    pub fn trigger_to (&mut self, triggers: Triggers) {
+        self.triggers = Some(triggers);
+    }
+    /// Handles `message` and returns the reply.
+    pub fn handle (&mut self, message: &OscMessage) -> OscMessage {
+        match self.dispatch(message) {
+            Ok(()) => self.reply(),
+            Err(e) => {
+                eprintln!("{}: {}", &message.addr, &e);
+                OscMessage::new("/lude/error", vec![OscArg::Str(e)])
+            }
+        }
+    }
+    fn dispatch (&mut self, message: &OscMessage) -> Result<(), String> {
+        let args = &message.args;
+        match message.addr.as_str() {
+            "/lude/start"  => self.status.start(),
+            "/lude/stop"   => self.status.stop(),
+            "/lude/status" => {},
+            "/lude/locate" => {
+                let frame = number(args, 0)?;
+                if frame < 0.0 { return Err(format!("can not locate to {}", frame)) }
+                self.status.locate(frame as FrameTime)
+            },
+            "/lude/marker" => {
+                let name = string(args, 0)?;
+                let document = self.latest.lock().unwrap();
+                let time = *document.markers.get(name)
+                    .ok_or_else(|| format!("no marker named {}", name))?;
+                // the next time it is heard, or else the first
+                let frame = document.replay_from(time, self.status.position())
+                    .or_else(|| document.replay_from(time, 0))
+                    .ok_or_else(|| format!("marker {} is never heard", name))?;
+                self.status.locate(frame)
+            },
+            "/lude/mute" | "/lude/solo" => {
+                let source = string(args, 0)?.to_string();
+                let on = args.len() < 2 || number(args, 1)? != 0.0;
+                let (muted, soloed) = {
+                    let mut overrides = self.overrides.lock().unwrap();
+                    let set = if message.addr == "/lude/mute" {
+                        &mut overrides.muted
+                    } else {
+                        &mut overrides.soloed
+                    };
+                    if on { set.insert(source); } else { set.remove(&source); }
+                    (overrides.muted.clone(), overrides.soloed.clone())
+                };
+                self.change(|document| {
+                    document.mute(muted, soloed);
+                    Ok(())
+                })?
+            },
+            "/lude/set" => {
+                let name = string(args, 0)?.to_string();
+                let value = number(args, 1)?;
+                if Document::WRITE_SETTINGS.contains(&name.as_str()) {
+                    return Err(format!("{} can only be set in the source file", name))
+                }
+                self.change(|document| document.set(&name, value))?;
+                self.overrides.lock().unwrap().settings.push((name, value))
+            },
+            "/lude/reload" => self.reload()?,
+            "/lude/trigger" => {
+                let source = string(args, 0)?;
+                let cue = if args.len() < 2 { None } else { Some(string(args, 1)?) };
+                self.triggers.as_ref()
+                    .ok_or_else(|| String::from("no sampler is running"))?
+                    .play(source, cue)?
+            },
+            _ => return Err(String::from("unknown address"))
+        }
+        Ok(())
+    }
+    /// Loads the document again and swaps it in at the next cycle.
+    fn reload (&mut self) -> Result<(), String> {
+        let document = catch_unwind(AssertUnwindSafe(|| (self.load)()))
+            .map_err(|_| String::from("failed to reload, keeping previous version"))?;
+        self.swap(document)
+    }
+    /// Changes a copy of the last version of the document that was
+    /// loaded with `change`, and swaps it in at the next cycle.
+    fn change<F> (&mut self, change: F) -> Result<(), String>
+    where F: FnOnce(&mut Document) -> Result<(), String> {
+        let mut document = self.latest.lock().unwrap().clone();
+        change(&mut document)?;
+        *self.latest.lock().unwrap() = document.clone();
+        self.swap(document)
+    }
+    fn swap (&self, document: Document) -> Result<(), String> {
+        self.swaps.send(Swap { playable: document, quantize: Quantize::Block })
+            .map_err(|_| String::from("playback has ended"))
+    }
+    fn reply (&self) -> OscMessage {
+        OscMessage::new("/lude/status", vec![
+            OscArg::Int(self.status.position() as i32),
+            OscArg::Int(self.status.rolling() as i32),
+            OscArg::Int(self.status.underruns() as i32),
+            OscArg::Int(self.status.finished() as i32),
+        ])
+    }
+}
+
+/// Receives control messages on UDP `port` of `host` in a background
+/// thread, and lets `server` handle them.
+pub fn listen (host: &str, port: u16, mut server: Server) {
+    let socket = UdpSocket::bind((host, port))
+        .unwrap_or_else(|e| panic!("Failed to listen on {}:{}: {}", host, port, e));
+    eprintln!("listening for OSC on {}:{}", host, port);
+    ThreadBuilder::new()
+        .name("lude osc".into())
+        .spawn(move || {
+            let mut packet = [0; 1536];
+            loop {
+                let (size, sender) = match socket.recv_from(&mut packet) {
+                    Ok(received) => received,
+                    Err(e) => { eprintln!("osc: {}", e); continue }
+                };
+                let reply = match OscMessage::decode(&packet[..size]) {
+                    Ok(message) => server.handle(&message),
+                    Err(e) => OscMessage::new("/lude/error", vec![OscArg::Str(e)])
+                };
+                if let Err(e) = socket.send_to(&reply.encode(), sender) {
+                    eprintln!("osc: {}", e)
+                }
+            }
+        })
+        .unwrap_or_else(|e| panic!("Failed to start OSC server: {:?}", e));
+}
+
+/// Sends messages to `target` (`HOST:PORT`) in a background thread,
+/// each once the player reaches it. Returns where to send the `Cue`s.
+/// Messages are dropped if the player locates before they are due.
+pub fn emit (target: &str, status: Arc<Status>) -> Sender<Cue> {
+    let socket = UdpSocket::bind(("0.0.0.0", 0))
+        .and_then(|socket| socket.connect(target).map(|_| socket))
+        .unwrap_or_else(|e| panic!("Failed to send OSC to {}: {}", target, e));
+    let (tx, rx) = channel::<Cue>();
+    ThreadBuilder::new()
+        .name("lude osc out".into())
+        .spawn(move || {
+            let mut sequencer = Sequencer::new();
+            while !status.stopped() {
+                loop {
+                    match rx.try_recv() {
+                        Ok(cue) => sequencer.schedule(cue.frame, cue),
+                        Err(TryRecvError::Empty) => break,
+                        Err(TryRecvError::Disconnected) => return
+                    }
+                }
+                for (_, cue) in sequencer.due(status.position()) {
+                    if cue.epoch != status.epoch() { continue }
+                    if let Err(e) = socket.send(&cue.message.encode()) {
+                        eprintln!("osc: {}", e)
+                    }
+                }
+                sleep(EMIT_INTERVAL)
+            }
+        })
+        .unwrap_or_else(|e| panic!("Failed to start OSC sender: {:?}", e));
+    tx
+}
+
+fn number (args: &[OscArg], index: usize) -> Result<f64, String> {
+    args.get(index).and_then(OscArg::number)
+        .ok_or_else(|| format!("argument {} must be a number", index + 1))
+}
+
+fn string (args: &[OscArg], index: usize) -> Result<&str, String> {
+    args.get(index).and_then(OscArg::string)
+        .ok_or_else(|| format!("argument {} must be a string", index + 1))
+}
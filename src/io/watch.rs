@@ -0,0 +1,46 @@
+//! reload a document when its source file changes
+
+use std::fs::metadata;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::Sender;
+use std::thread::{Builder as ThreadBuilder, sleep};
+use std::time::{Duration, SystemTime};
+use super::engine::{Quantize, Swap};
+
+/// How often the source file is checked for changes.
+pub const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Watches the file at `path` and, whenever it is modified, calls `load`
+/// and sends the result through `swaps` to be swapped in at the next
+/// `quantize` point. If `load` panics (e.g. on a syntax error),
+/// the change is skipped and whatever is playing keeps playing.
+pub fn watch<P, F> (path: &str, quantize: Quantize, load: F, swaps: Sender<Swap<P>>)
+where
+    P: Send + 'static,
+    F: Fn(&str) -> P + Send + 'static
+{
+    let path = path.to_string();
+    ThreadBuilder::new()
+        .name("lude watcher".into())
+        .spawn(move || {
+            let mut modified = modified_at(&path);
+            loop {
+                sleep(WATCH_INTERVAL);
+                let now = modified_at(&path);
+                if now == modified { continue }
+                modified = now;
+                eprintln!("reloading {}", &path);
+                match catch_unwind(AssertUnwindSafe(|| load(&path))) {
+                    Ok(playable) => {
+                        if swaps.send(Swap { playable, quantize }).is_err() { break }
+                    },
+                    Err(_) => eprintln!("failed to reload {}, keeping previous version", &path)
+                }
+            }
+        })
+        .unwrap_or_else(|e| panic!("Failed to start watcher: {:?}", e));
+}
+
+fn modified_at (path: &str) -> Option<SystemTime> {
+    metadata(path).and_then(|meta| meta.modified()).ok()
+}
@@ -16,6 +16,8 @@
 mod types;
 mod eval;
 mod document;
+mod timeline;
+mod resource;
 mod render;
 mod media;
 mod io;
@@ -28,33 +30,438 @@ extern crate pest;
 //#[macro_use] extern crate lazy_static;
 //#[macro_use] extern crate debug_stub_derive;
 
-use std::env;
 use std::process::exit;
-use std::fs::read_to_string;
+use std::fs::{read as read_bytes, read_to_string, write};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::channel;
+use clap::{App, Arg, ArgMatches, SubCommand};
 
 pub use types::*;
-pub use eval::{read, eval};
+pub use eval::{read, eval, relink};
 pub use render::{render, to_channels, to_frames};
 pub use document::Document;
-use io::file::write_to_file;
+use io::file::{cue_points, read_provenance, write_to_file, Provenance, WriteOptions};
+use io::engine::{play, Controls, Quantize, DEFAULT_LATENCY};
+use io::dummy::DummyBackend;
+use io::jack::JackBackend;
+use io::watch::watch;
+use io::midi::{export, import, NoteMap};
+use io::udp_osc::{emit, listen, Overrides, Server};
+use media::SoundMap;
+use media::onsets::DEFAULT_SENSITIVITY;
+use media::sampler::Sampler;
 
-/// Takes a source file and renders it to a file.
+/// Takes a source file and renders it to a file,
+/// or plays it back through JACK.
+///
+/// * `lude SOURCE_FILE` renders to `output.wav`.
+/// * `lude render SOURCE_FILE [OUTPUT_FILE]` renders to `OUTPUT_FILE`,
+///   with the document's markers as cue points.
+/// * `lude render --cue-events SOURCE_FILE [OUTPUT_FILE]` also marks
+///   where every event starts.
+/// * `lude extract OUTPUT_FILE [SOURCE_FILE]` recovers the source code
+///   that a rendered file was made from.
+/// * `lude export --midi MIDI_FILE [--map MAP_FILE] SOURCE_FILE` converts
+///   to a Standard MIDI File (see `io::midi`).
+/// * `lude import --midi MIDI_FILE --map MAP_FILE [SOURCE_FILE]` converts
+///   a Standard MIDI File to source code.
+/// * `lude resources SOURCE_FILE` lists the files a document is made from.
+/// * `lude resources --relink NAME PATH SOURCE_FILE` points the resource
+///   named `NAME` to the file at `PATH`, rewriting the source file.
+/// * `lude info [--onsets [--sensitivity PERCENT]] MEDIA_FILE` describes
+///   a media file, optionally listing the hits in it (see `media::onsets`).
+/// * `lude play SOURCE_FILE` plays the document through JACK.
+/// * `lude play --dummy SOURCE_FILE` plays the document without a sound server.
+/// * `lude play --transport [--timebase] SOURCE_FILE` follows JACK transport,
+///   optionally publishing the document's tempo as timebase master.
+/// * `lude play --watch [--quantize block|bar|marker] SOURCE_FILE` reloads
+///   the document whenever the source file changes, without stopping.
+/// * `lude play --osc PORT SOURCE_FILE` accepts OSC control messages
+///   on UDP `PORT` (see `io::udp_osc`), including ones that play sources
+///   from their cue points (see `media::sampler`). Only messages sent from
+///   this machine arrive, unless `--osc-host` names another address to
+///   listen on, such as `0.0.0.0`.
+/// * `lude play --osc-target HOST:PORT SOURCE_FILE` sends the document's
+///   OSC messages to `HOST:PORT` as they are played.
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() == 1 {
-        println!("Usage: lude SOURCE_FILE");
+    let source_arg = || Arg::with_name("SOURCE_FILE")
+        .help("The Lude source file")
+        .required(true)
+        .index(1);
+    let matches = App::new("lude")
+        .about("A language for painting with time.")
+        .arg(Arg::with_name("SOURCE_FILE")
+            .help("Renders this source file to output.wav")
+            .index(1))
+        .subcommand(SubCommand::with_name("render")
+            .about("Renders a source file to a file")
+            .arg(source_arg())
+            .arg(Arg::with_name("OUTPUT_FILE")
+                .help("Where to write the rendered output")
+                .default_value("output.wav")
+                .index(2))
+            .arg(Arg::with_name("cue-events")
+                .long("cue-events")
+                .help("Marks where every event starts, as well as every marker")))
+        .subcommand(SubCommand::with_name("extract")
+            .about("Recovers the source file of a rendered file")
+            .arg(Arg::with_name("OUTPUT_FILE")
+                .help("A file rendered by Lude")
+                .required(true)
+                .index(1))
+            .arg(Arg::with_name("SOURCE_FILE")
+                .help("Where to write the source code, instead of stdout")
+                .index(2)))
+        .subcommand(SubCommand::with_name("export")
+            .about("Converts a source file to another format")
+            .arg(source_arg())
+            .arg(Arg::with_name("midi")
+                .long("midi")
+                .takes_value(true)
+                .value_name("MIDI_FILE")
+                .required(true)
+                .help("Writes a Standard MIDI File with one track per source"))
+            .arg(Arg::with_name("map")
+                .long("map")
+                .takes_value(true)
+                .value_name("MAP_FILE")
+                .help("Reads which note stands for which source from this file")))
+        .subcommand(SubCommand::with_name("import")
+            .about("Converts a Standard MIDI File to a source file")
+            .arg(Arg::with_name("midi")
+                .long("midi")
+                .takes_value(true)
+                .value_name("MIDI_FILE")
+                .required(true)
+                .help("The Standard MIDI File to read"))
+            .arg(Arg::with_name("map")
+                .long("map")
+                .takes_value(true)
+                .value_name("MAP_FILE")
+                .required(true)
+                .help("Reads which note stands for which source from this file"))
+            .arg(Arg::with_name("SOURCE_FILE")
+                .help("Where to write the source code, instead of stdout")
+                .index(1)))
+        .subcommand(SubCommand::with_name("resources")
+            .about("Lists the resources a source file uses")
+            .arg(source_arg())
+            .arg(Arg::with_name("relink")
+                .long("relink")
+                .takes_value(true)
+                .number_of_values(2)
+                .value_names(&["NAME", "PATH"])
+                .help("Points a resource to another file, rewriting the source file")))
+        .subcommand(SubCommand::with_name("info")
+            .about("Describes a media file")
+            .arg(Arg::with_name("MEDIA_FILE")
+                .help("The media file to describe")
+                .required(true)
+                .index(1))
+            .arg(Arg::with_name("onsets")
+                .long("onsets")
+                .help("Lists where the hits in it start, as used by |#n| slices"))
+            .arg(Arg::with_name("sensitivity")
+                .long("sensitivity")
+                .takes_value(true)
+                .value_name("PERCENT")
+                .requires("onsets")
+                .help("How readily hits are detected, from 0 to 100 (default 50)")))
+        .subcommand(SubCommand::with_name("play")
+            .about("Plays a source file through JACK")
+            .arg(source_arg())
+            .arg(Arg::with_name("dummy")
+                .long("dummy")
+                .help("Simulates playback without a sound server"))
+            .arg(Arg::with_name("transport")
+                .long("transport")
+                .help("Follows JACK transport start, stop and locate"))
+            .arg(Arg::with_name("timebase")
+                .long("timebase")
+                .requires("transport")
+                .help("Publishes the document's tempo as JACK timebase master"))
+            .arg(Arg::with_name("watch")
+                .long("watch")
+                .help("Reloads the source file whenever it changes"))
+            .arg(Arg::with_name("quantize")
+                .long("quantize")
+                .takes_value(true)
+                .possible_values(&["block", "bar", "marker"])
+                .requires("watch")
+                .help("Where a reloaded document takes over"))
+            .arg(Arg::with_name("osc")
+                .long("osc")
+                .takes_value(true)
+                .value_name("PORT")
+                .help("Accepts OSC control messages on this UDP port"))
+            .arg(Arg::with_name("osc-host")
+                .long("osc-host")
+                .takes_value(true)
+                .value_name("HOST")
+                .default_value("127.0.0.1")
+                .help("The address to accept OSC control messages on"))
+            .arg(Arg::with_name("osc-target")
+                .long("osc-target")
+                .takes_value(true)
+                .value_name("HOST:PORT")
+                .help("Sends the document's OSC messages here")))
+        .get_matches();
+    match matches.subcommand() {
+        ("render", Some(args)) => render_to_file(
+            args.value_of("SOURCE_FILE").unwrap(),
+            args.value_of("OUTPUT_FILE").unwrap(),
+            args.is_present("cue-events")
+        ),
+        ("extract", Some(args)) => extract_file(args),
+        ("export", Some(args)) => export_file(args),
+        ("import", Some(args)) => import_file(args),
+        ("resources", Some(args)) => resources_file(args),
+        ("info", Some(args)) => describe_media(args),
+        ("play", Some(args)) => play_file(args),
+        _ => match matches.value_of("SOURCE_FILE") {
+            Some(path) => render_to_file(path, "output.wav", false),
+            None => {
+                println!("{}", matches.usage());
+                exit(1);
+            }
+        }
+    }
+}
+
+/// Plays the source file named by the `SOURCE_FILE` argument.
+fn play_file (args: &ArgMatches) {
+    let path = args.value_of("SOURCE_FILE").unwrap().to_string();
+    let document = load(args);
+    let overrides = Arc::new(Mutex::new(Overrides::default()));
+    let latest = Arc::new(Mutex::new(document.clone()));
+    let reload = {
+        let overrides = overrides.clone();
+        let latest = latest.clone();
+        move |path: &str| {
+            let mut document = load_file(path);
+            overrides.lock().unwrap().apply(&mut document);
+            *latest.lock().unwrap() = document.clone();
+            document
+        }
+    };
+    let mut controls = Controls::new();
+    let (swaps, swapped) = channel();
+    if args.is_present("watch") {
+        let quantize = match args.value_of("quantize") {
+            Some("bar")    => Quantize::Bar,
+            Some("marker") => Quantize::Marker,
+            _              => Quantize::Block
+        };
+        watch(&path, quantize, reload.clone(), swaps.clone());
+    }
+    if let Some(port) = args.value_of("osc") {
+        let port = port.parse().unwrap_or_else(|_| {
+            eprintln!("invalid port: {}", port);
+            exit(1);
+        });
+        let mut server = Server::new(
+            controls.status.clone(), swaps, overrides, move || reload(&path), latest
+        );
+        let sampler = load_sampler(&document);
+        server.trigger_to(sampler.triggers());
+        controls.sampler = Some(sampler);
+        listen(args.value_of("osc-host").unwrap(), port, server);
+    }
+    controls.swaps = Some(swapped);
+    if let Some(target) = args.value_of("osc-target") {
+        controls.cues = Some(emit(target, controls.status.clone()));
+    }
+    if args.is_present("dummy") {
+        let mut backend = DummyBackend::new(SAMPLE_RATE, 1024);
+        backend.realtime = true;
+        play(document, &mut backend, DEFAULT_LATENCY, controls);
+    } else {
+        let mut backend = JackBackend::new();
+        backend.transport = args.is_present("transport");
+        backend.hold = backend.transport
+            || args.is_present("watch")
+            || args.is_present("osc");
+        if args.is_present("timebase") {
+            backend.timebase = Some(document.tempo.unwrap_or_default());
+        }
+        play(document, &mut backend, DEFAULT_LATENCY, controls);
+    }
+}
+
+/// Loads every source of `document` into a sampler, by name,
+/// so that they can be triggered from their cue points.
+fn load_sampler (document: &Document) -> Sampler {
+    let mut sampler = Sampler::new(document.channels().max(1));
+    for (_, resource) in document.resources.list() {
+        if let Some(path) = &resource.path {
+            if let Err(e) = sampler.load(&resource.name, path) {
+                eprintln!("{}: {}", path, e)
+            }
+        }
+    }
+    sampler
+}
+
+/// Exports the source file named by the `SOURCE_FILE` argument
+/// to a Standard MIDI File.
+fn export_file (args: &ArgMatches) {
+    let notes = load_notes(args.value_of("map"));
+    let path = args.value_of("midi").unwrap();
+    let midi = export(&load(args), &notes).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1);
+    });
+    write(path, midi).expect("cannot write MIDI file");
+    eprintln!("wrote {}", path);
+}
+
+/// Converts the Standard MIDI File named by the `midi` argument
+/// to source code.
+fn import_file (args: &ArgMatches) {
+    let notes = load_notes(args.value_of("map"));
+    let path = args.value_of("midi").unwrap();
+    let midi = read_bytes(path).expect("cannot read MIDI file");
+    let source = import(&midi, &notes).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
         exit(1);
+    });
+    match args.value_of("SOURCE_FILE") {
+        Some(path) => write(path, source).expect("cannot write source file"),
+        None => print!("{}", source)
+    }
+}
+
+/// With `--relink NAME PATH`, points the resource named `NAME` in the
+/// source file named by the `SOURCE_FILE` argument to the file at `PATH`
+/// and writes the source file back. Then lists the resources it uses.
+fn resources_file (args: &ArgMatches) {
+    let mut document = load(args);
+    if let Some(mut values) = args.values_of("relink") {
+        let (name, path) = (values.next().unwrap(), values.next().unwrap());
+        let source_file = args.value_of("SOURCE_FILE").unwrap();
+        let id = document.resources.find(name).unwrap_or_else(|| {
+            eprintln!("no resource named {}", name);
+            exit(1);
+        });
+        let from = document.resources.path(id).to_string();
+        let source = read_to_string(source_file).expect("cannot read file");
+        let relinked = relink(&source, &from, path)
+            .and_then(|source| document.relink(id, path).map(|_| source))
+            .unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                exit(1);
+            });
+        write(source_file, relinked).expect("cannot write source file");
+    }
+    list_resources(&document);
+}
+
+/// Prints the resources of `document`, one per line.
+fn list_resources (document: &Document) {
+    println!("id\tname\trate\tchannels\tlength\thash\tpath");
+    for (id, resource) in document.resources.list() {
+        println!("{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            id, resource.name,
+            resource.rate, resource.channels, resource.length,
+            resource.hash().map(|hash| format!("{:016x}", hash)).unwrap_or_default(),
+            resource.path.as_deref().unwrap_or(""));
+    }
+}
+
+/// Prints the sample rate, channel count and length of the media file
+/// named by the `MEDIA_FILE` argument, and with `--onsets`, the index,
+/// frame and time of every onset in it.
+fn describe_media (args: &ArgMatches) {
+    let path = args.value_of("MEDIA_FILE").unwrap();
+    let media = SoundMap::new();
+    let (rate, channels, length) = media.info(path);
+    println!("rate\t{}\nchannels\t{}\nlength\t{}", rate, channels, length);
+    if args.is_present("onsets") {
+        let sensitivity = match args.value_of("sensitivity") {
+            None => DEFAULT_SENSITIVITY,
+            Some(percent) => match percent.parse::<f64>() {
+                Ok(percent) if (0.0..=100.0).contains(&percent) => percent / 100.0,
+                _ => {
+                    eprintln!("invalid sensitivity: {}", percent);
+                    exit(1);
+                }
+            }
+        };
+        println!("\nonset\tframe\tseconds");
+        for (index, frame) in media.onsets(path, sensitivity).iter().enumerate() {
+            println!("#{}\t{}\t{:.3}", index, frame, *frame as f64 / rate.max(1) as f64);
+        }
+    }
+}
+
+/// Reads the note mapping file at `path`, if any.
+fn load_notes (path: Option<&str>) -> NoteMap {
+    match path {
+        None => NoteMap::default(),
+        Some(path) => {
+            let text = read_to_string(path).expect("cannot read mapping file");
+            NoteMap::parse(&text).unwrap_or_else(|e| {
+                eprintln!("{}: {}", path, e);
+                exit(1);
+            })
+        }
     }
-    let filename = &args[1];
+}
+
+/// Reads and evaluates the source file named by the `SOURCE_FILE` argument.
+fn load (args: &ArgMatches) -> Document {
+    load_file(args.value_of("SOURCE_FILE").unwrap())
+}
+
+/// Reads and evaluates the source file at `filename`.
+fn load_file (filename: &str) -> Document {
     let source = read_to_string(filename).expect("cannot read file");
     //eprintln!("{:#?}", &source);
     let parsed = read(&source);
     //eprintln!("{:#?}", &parsed);
-    let document = eval(parsed);
-    //eprintln!("{:#?}", &document);
-    let (_, max, longest) = document.bounds();
-    let rendered = render(&document, 0, max + longest);
-    let output = to_frames(to_channels(rendered));
-    //eprintln!("{:#?}", &output);
-    write_to_file(output, "output.wav");
+    eval(parsed)
+}
+
+/// Renders the whole document in the source file at `source_file`
+/// to the file at `path`, marking every marker of the document, and
+/// every event start if `events` is set, as a cue point. The source code
+/// and render settings are kept in the metadata of the file.
+fn render_to_file (source_file: &str, path: &str, events: bool) {
+    let source = read_to_string(source_file).expect("cannot read file");
+    let document = eval(read(&source));
+    let end = document.duration().unwrap_or_else(|| {
+        eprintln!("can not render an endless loop to a file, try `lude play`");
+        exit(1);
+    });
+    let rendered = render(&document, 0, end);
+    let channels = document.channels().max(1);
+    let output = to_frames(to_channels(rendered, channels));
+    write_to_file(output, path, &WriteOptions {
+        channels,
+        cues:       cue_points(&document, events),
+        provenance: Some(Provenance::new(&document, &source))
+    });
+}
+
+/// Recovers the source code from the metadata of the rendered file
+/// named by the `OUTPUT_FILE` argument, and prints how it was rendered.
+fn extract_file (args: &ArgMatches) {
+    let path = args.value_of("OUTPUT_FILE").unwrap();
+    let provenance = read_provenance(path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        exit(1);
+    });
+    eprintln!("rendered by lude {} at {} Hz from source code with hash {:016x}",
+        provenance.version, provenance.rate, provenance.hash);
+    for source in provenance.sources.iter() {
+        eprintln!("using {}", source);
+    }
+    let source = provenance.source.unwrap_or_else(|| {
+        eprintln!("{}: the source code was too long to be kept, only its hash was", path);
+        exit(1);
+    });
+    match args.value_of("SOURCE_FILE") {
+        Some(path) => write(path, source).expect("cannot write source file"),
+        None => print!("{}", source)
+    }
 }
@@ -0,0 +1,78 @@
+//! named points in a source file, with the audio that follows them
+//!
+//! So that playback can start from a cue point without waiting
+//! for the disk, the first `N_CUE_BUFFER_FRAMES` frames after
+//! every cue point are kept in memory.
+
+use indexmap::IndexMap;
+use super::sndfile::{SndFile, OpenMode, SeekMode};
+use crate::types::FrameTime;
+
+/// How many frames are kept in memory after each cue point.
+pub const N_CUE_BUFFER_FRAMES: usize = 1 << 14; // 16384
+
+/// The cue points of one source file, each with the frames that follow it.
+#[derive(Debug)]
+pub struct CuePointManager {
+    path:     String,
+    channels: usize,
+    labels:   IndexMap<String, FrameTime>,
+    buffers:  IndexMap<String, Vec<f32>>,
+}
+
+impl CuePointManager {
+    /// Reads the cue points of the source at `path`
+    /// and the frames that follow each of them.
+    pub fn new (path: &str) -> Result<CuePointManager, String> {
+        let file = SndFile::new(path, OpenMode::Read)?;
+        let channels = file.get_sndinfo().channels as usize;
+        let mut cues = CuePointManager {
+            path: path.to_string(),
+            channels,
+            labels:  IndexMap::new(),
+            buffers: IndexMap::new()
+        };
+        for cue in file.get_cues() {
+            cues.set(&cue.name, cue.position as FrameTime)?;
+        }
+        file.close();
+        Ok(cues)
+    }
+    /// Sets the cue point `label` to `position`, adding it if it is new,
+    /// and reads the frames that follow it.
+    pub fn set (&mut self, label: &str, position: FrameTime) -> Result<(), String> {
+        let buffer = self.read(position)?;
+        self.labels.insert(label.to_string(), position);
+        self.buffers.insert(label.to_string(), buffer);
+        Ok(())
+    }
+    /// Reads up to `N_CUE_BUFFER_FRAMES` interleaved frames from `position`.
+    pub fn read (&self, position: FrameTime) -> Result<Vec<f32>, String> {
+        let mut file = SndFile::new(&self.path, OpenMode::Read)?;
+        let mut buffer = vec![0.0; N_CUE_BUFFER_FRAMES * self.channels];
+        let mut read = 0;
+        if file.seek(position as i64, SeekMode::SeekSet) >= 0 {
+            read = file.readf_f32(&mut buffer, N_CUE_BUFFER_FRAMES as i64).max(0) as usize;
+        }
+        file.close();
+        buffer.truncate(read * self.channels);
+        Ok(buffer)
+    }
+    /// Gets the position of the cue point at `index`.
+    pub fn position (&self, index: usize) -> FrameTime {
+        *self.labels.get_index(index).unwrap().1
+    }
+    /// Gets the interleaved frames that follow the cue point at `index`.
+    /// There are fewer than `N_CUE_BUFFER_FRAMES` near the end of the source.
+    pub fn buffer (&self, index: usize) -> &[f32] {
+        self.buffers.get_index(index).unwrap().1
+    }
+    /// Lists the cue points, in the order they were added.
+    pub fn labels (&self) -> impl Iterator<Item = (&str, FrameTime)> {
+        self.labels.iter().map(|(label, position)| (label.as_str(), *position))
+    }
+    /// Number of channels of the source.
+    pub fn channels (&self) -> usize {
+        self.channels
+    }
+}
@@ -1,50 +1,128 @@
 pub mod sndfile;
 mod sndfile_ffi;
+pub mod cues;
+pub mod onsets;
+pub mod sampler;
 
 use std::cell::RefCell;
 use std::collections::HashMap;
-use sndfile::{SndFile, OpenMode, SeekMode};
+use sndfile::{SndFile, OpenMode, SeekMode, CuePoint};
 use crate::types::{Frame, FrameTime};
 
 #[derive(Debug)]
 pub struct SoundMap {
+    /// Open sources, by path. Every copy of the map opens its own,
+    /// so that copies used by different threads never share one.
     sounds: RefCell<HashMap<String, SndFile>>,
     durations: RefCell<HashMap<String, FrameTime>>,
+    cues: RefCell<HashMap<String, Vec<CuePoint>>>,
+    /// Onsets by path and sensitivity, which is kept as its bits.
+    onsets: RefCell<HashMap<(String, u64), Vec<FrameTime>>>,
+}
+
+impl Clone for SoundMap {
+    /// Copies what is known about the sources, but none of the open ones.
+    fn clone (&self) -> SoundMap {
+        SoundMap {
+            sounds: RefCell::new(HashMap::new()),
+            durations: self.durations.clone(),
+            cues: self.cues.clone(),
+            onsets: self.onsets.clone()
+        }
+    }
 }
 
 impl SoundMap {
     pub fn new () -> SoundMap {
         SoundMap {
             sounds: RefCell::new(HashMap::new()),
-            durations: RefCell::new(HashMap::new())
+            durations: RefCell::new(HashMap::new()),
+            cues: RefCell::new(HashMap::new()),
+            onsets: RefCell::new(HashMap::new())
         }
     }
-    fn get_sound (&self, path: &str) -> SndFile {
+    /// Calls `use_sound` with the source at `path`, opening it first
+    /// if this map has not opened it yet.
+    fn with_sound<T, F: FnOnce(&mut SndFile) -> T> (&self, path: &str, use_sound: F) -> T {
         let mut sounds = self.sounds.borrow_mut();
-        let sound = sounds.get(path);
-        match sound {
-            Some(sound) => sound.clone(),
-            None => {
-                let sound = SndFile::new(path, OpenMode::Read).unwrap();
-                sounds.insert(path.to_string(), sound.clone());
-                sound
-            }
-        }.clone()
+        let sound = sounds.entry(path.to_string()).or_insert_with(|| {
+            let sound = SndFile::new(path, OpenMode::Read).unwrap();
+            self.cues.borrow_mut().entry(path.to_string()).or_insert_with(|| sound.get_cues());
+            sound
+        });
+        use_sound(sound)
     }
     pub fn get_duration (&self, path: &str) -> FrameTime {
-        let sound = self.get_sound(path);
-        let info = sound.get_sndinfo();
+        let info = self.with_sound(path, |sound| sound.get_sndinfo());
         info.frames as FrameTime
     }
-    pub fn get_frame (&self, path: &str, frame: i64) -> Option<Frame> {
-        if frame < 0 { return None }
-        let mut sound = self.get_sound(path);
-        let info = sound.get_sndinfo();
-        let channels = info.channels;
-        let mut frames = vec![0; channels as usize];
-        sound.seek(frame, SeekMode::SeekSet);
-        sound.readf_i16(frames.as_mut_slice(), 1);
-        Some(frames)
+    /// Gets the sample rate, channel count and length of the source at `path`.
+    pub fn info (&self, path: &str) -> (usize, usize, FrameTime) {
+        let info = self.with_sound(path, |sound| sound.get_sndinfo());
+        (info.samplerate as usize, info.channels as usize, self.get_source_length(path))
+    }
+    /// Gets the cue points of the source at `path`,
+    /// as they were when it was first opened.
+    pub fn cues (&self, path: &str) -> Vec<CuePoint> {
+        if let Some(cues) = self.cues.borrow().get(path) {
+            return cues.clone()
+        }
+        self.with_sound(path, |_| ());
+        self.cues.borrow()[path].clone()
+    }
+    /// Gets the onsets of the source at `path` (see `onsets`), which are
+    /// only detected the first time they are asked for with `sensitivity`.
+    pub fn onsets (&self, path: &str, sensitivity: f64) -> Vec<FrameTime> {
+        let key = (path.to_string(), sensitivity.to_bits());
+        if let Some(onsets) = self.onsets.borrow().get(&key) {
+            return onsets.clone()
+        }
+        let onsets = onsets::detect(path, sensitivity).unwrap();
+        self.onsets.borrow_mut().insert(key, onsets.clone());
+        onsets
+    }
+    /// Gets the frame nearest to `frame`, and at most `window` frames away,
+    /// at which the sum of the channels of the source at `path` crosses zero,
+    /// or `frame` itself if there is no such frame.
+    pub fn zero_crossing (&self, path: &str, frame: FrameTime, window: FrameTime) -> FrameTime {
+        let start = frame.saturating_sub(window + 1);
+        let end = (frame + window + 1).min(self.get_source_length(path));
+        if end <= start { return frame }
+        let (samples, channels, read) = self.with_sound(path, |sound| {
+            let channels = (sound.get_sndinfo().channels as usize).max(1);
+            let mut samples = vec![0; (end - start) * channels];
+            let read = if sound.seek(start as i64, SeekMode::SeekSet) == start as i64 {
+                sound.readf_i16(samples.as_mut_slice(), (end - start) as i64).max(0) as usize
+            } else {
+                0
+            };
+            (samples, channels, read)
+        });
+        let mono: Vec<i32> = samples[..read * channels].chunks(channels)
+            .map(|frame| frame.iter().map(|sample| *sample as i32).sum())
+            .collect();
+        (1..mono.len())
+            .filter(|index| mono[*index] == 0 || (mono[index - 1] < 0) != (mono[*index] < 0))
+            .map(|index| {
+                // of the two frames around the crossing, the one closer to zero
+                let index = if mono[index - 1].abs() < mono[index].abs() { index - 1 } else { index };
+                start + index
+            })
+            .filter(|crossing| (*crossing as i64 - frame as i64).abs() <= window as i64)
+            .min_by_key(|crossing| (*crossing as i64 - frame as i64).abs())
+            .unwrap_or(frame)
+    }
+    /// Gets the frame at `frame` of the source at `path`, which is silent
+    /// outside the source, or where the source can not be read.
+    pub fn get_frame (&self, path: &str, frame: i64) -> Frame {
+        self.with_sound(path, |sound| {
+            let info = sound.get_sndinfo();
+            let mut frames = vec![0; info.channels as usize];
+            let read = frame >= 0 && frame < info.frames
+                && sound.seek(frame, SeekMode::SeekSet) == frame
+                && sound.readf_i16(frames.as_mut_slice(), 1) == 1;
+            if read { frames } else { vec![0; info.channels as usize] }
+        })
     }
     pub fn get_source_length (&self, event: &str) -> FrameTime {
         let mut durations = self.durations.borrow_mut();
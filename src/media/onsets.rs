@@ -0,0 +1,109 @@
+//! finds where the hits in a source start
+//!
+//! The source is mixed down to mono and cut into blocks of
+//! `BLOCK_FRAMES` frames. An **onset** is where the loudness of
+//! a block rises sharply above that of the blocks before it; how
+//! sharply is set by the **sensitivity**, from 0 (only the most
+//! obvious hits) to 1 (every little bump). The onset is then placed
+//! at the first frame since the quietest of those earlier blocks
+//! that is nearly as loud as the loudest frame of the block.
+
+use super::sndfile::{SndFile, OpenMode};
+use crate::types::FrameTime;
+
+/// How many frames of the source are measured at once.
+const BLOCK_FRAMES: usize = 256;
+
+/// How many earlier blocks a block is compared to.
+const LOOKBACK_BLOCKS: usize = 2;
+
+/// How many blocks there are at least between two onsets (about 35 ms).
+const MIN_GAP_BLOCKS: usize = 6;
+
+/// Blocks quieter than this, relative to the loudest block, count as silent.
+const FLOOR_DB: f32 = -60.0;
+
+/// How much louder than before a block must be to start a hit
+/// at the highest sensitivity, and how much more at the lowest.
+const MIN_RISE_DB: f32 = 3.0;
+const RISE_RANGE_DB: f32 = 24.0;
+
+/// How loud the frame that an onset is placed at must be,
+/// relative to the loudest frame of its block.
+const ATTACK_RATIO: f32 = 0.5;
+
+/// The sensitivity that is used unless another one is set.
+pub const DEFAULT_SENSITIVITY: f64 = 0.5;
+
+/// Finds the onsets of the source at `path`, in order,
+/// with a `sensitivity` between 0 and 1.
+pub fn detect (path: &str, sensitivity: f64) -> Result<Vec<FrameTime>, String> {
+    Ok(find(&read_mono(path)?, sensitivity))
+}
+
+/// Finds the onsets in `samples`, with a `sensitivity` between 0 and 1.
+pub fn find (samples: &[f32], sensitivity: f64) -> Vec<FrameTime> {
+    let blocks: Vec<&[f32]> = samples.chunks(BLOCK_FRAMES).collect();
+    let loudness: Vec<f32> = blocks.iter().map(|block| {
+        let power = block.iter().map(|sample| sample * sample).sum::<f32>() / block.len() as f32;
+        10.0 * (power + 1e-12).log10()
+    }).collect();
+    let peak = loudness.iter().cloned().fold(f32::MIN, f32::max);
+    let floor = peak + FLOOR_DB;
+    let loudness: Vec<f32> = loudness.into_iter().map(|db| db.max(floor)).collect();
+    let rises: Vec<f32> = (0..loudness.len()).map(|index| {
+        let before = loudness[index.saturating_sub(LOOKBACK_BLOCKS)..index].iter()
+            .cloned()
+            .fold(loudness[index], f32::min);
+        loudness[index] - if index == 0 { floor } else { before }
+    }).collect();
+    let sensitivity = sensitivity.clamp(0.0, 1.0) as f32;
+    let threshold = MIN_RISE_DB + (1.0 - sensitivity) * RISE_RANGE_DB;
+    let mut onsets = Vec::new();
+    let mut last: Option<usize> = None;
+    for (index, rise) in rises.iter().enumerate() {
+        if *rise < threshold { continue }
+        // only the steepest rise among its neighbours starts a hit
+        let neighbours = &rises[index.saturating_sub(LOOKBACK_BLOCKS)..
+            (index + LOOKBACK_BLOCKS + 1).min(rises.len())];
+        if neighbours.iter().any(|other| other > rise) { continue }
+        if let Some(last) = last {
+            if index - last < MIN_GAP_BLOCKS { continue }
+        }
+        last = Some(index);
+        // the hit may already start in the blocks after the quietest one before it
+        let first = (index.saturating_sub(LOOKBACK_BLOCKS)..index)
+            .rev()
+            .min_by(|a, b| loudness[*a].partial_cmp(&loudness[*b]).unwrap())
+            .map_or(index, |quietest| quietest + 1);
+        let start = first * BLOCK_FRAMES;
+        let end = ((index + 1) * BLOCK_FRAMES).min(samples.len());
+        onsets.push(start + attack(&samples[start..end], blocks[index]));
+    }
+    onsets
+}
+
+/// Gets the offset of the first frame in `samples` that is nearly
+/// as loud as the loudest one in `block`.
+fn attack (samples: &[f32], block: &[f32]) -> usize {
+    let loudest = block.iter().fold(0.0f32, |loudest, sample| loudest.max(sample.abs()));
+    samples.iter()
+        .position(|sample| sample.abs() >= loudest * ATTACK_RATIO)
+        .unwrap_or(0)
+}
+
+/// Reads the source at `path`, averaging its channels.
+fn read_mono (path: &str) -> Result<Vec<f32>, String> {
+    let mut file = SndFile::new(path, OpenMode::Read)?;
+    let channels = (file.get_sndinfo().channels as usize).max(1);
+    let mut samples = Vec::new();
+    let mut buffer = vec![0.0; BLOCK_FRAMES * 16 * channels];
+    loop {
+        let read = file.readf_f32(&mut buffer, (BLOCK_FRAMES * 16) as i64).max(0) as usize;
+        if read == 0 { break }
+        samples.extend(buffer[..read * channels].chunks(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32));
+    }
+    file.close();
+    Ok(samples)
+}
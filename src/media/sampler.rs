@@ -0,0 +1,292 @@
+//! plays sources from their cue points, on demand
+//!
+//! Every `Sound` starts playing from the frames that its
+//! `CuePointManager` keeps in memory, while the playback engine
+//! streams the rest of the source from disk, starting right after
+//! them, on one worker thread for all sounds. Playback is requested
+//! from any thread through `Triggers`, and mixed by `Sampler::process`
+//! in the realtime thread, which never allocates, locks, or waits
+//! for the disk.
+
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::JoinHandle;
+use indexmap::IndexMap;
+use crate::io::engine::{Player, Playable, Renderer, Status, MAX_CYCLE_FRAMES, prepare, spawn_all};
+use crate::types::FrameTime;
+use super::cues::{CuePointManager, N_CUE_BUFFER_FRAMES};
+use super::sndfile::{SndFile, OpenMode, SeekMode};
+
+/// How many frames are read from disk at once while streaming.
+const STREAM_BLOCK_FRAMES: usize = 4096;
+
+/// Plays from the start of the source instead of a cue point.
+const FROM_START: usize = 0;
+
+/// The cue point labels and trigger of each sound, by name.
+type TriggerMap = IndexMap<String, (Vec<String>, Arc<Trigger>)>;
+
+/// A source file, read block by block, for the engine to stream.
+struct Stream {
+    file:     RefCell<SndFile>,
+    channels: usize,
+    length:   FrameTime,
+    /// The frame at which `block` starts, and its interleaved samples.
+    block:    RefCell<(FrameTime, Vec<f32>)>,
+}
+
+impl Playable for Stream {
+    fn channels (&self) -> usize {
+        self.channels
+    }
+    fn len (&self) -> FrameTime {
+        self.length
+    }
+    fn frame (&self, index: FrameTime, frame: &mut [f32]) {
+        let mut block = self.block.borrow_mut();
+        let (start, samples) = &mut *block;
+        if index < *start || index >= *start + samples.len() / self.channels {
+            let mut file = self.file.borrow_mut();
+            samples.resize(STREAM_BLOCK_FRAMES * self.channels, 0.0);
+            let read = if file.seek(index as i64, SeekMode::SeekSet) < 0 { 0 } else {
+                file.readf_f32(samples, STREAM_BLOCK_FRAMES as i64).max(0) as usize
+            };
+            samples.truncate(read * self.channels);
+            *start = index;
+        }
+        let offset = (index - *start) * self.channels;
+        for (channel, sample) in frame.iter_mut().enumerate() {
+            *sample = samples.get(offset + channel).cloned().unwrap_or(0.0);
+        }
+    }
+}
+
+/// Where a `Sound` was last asked to play from.
+#[derive(Debug, Default)]
+struct Trigger {
+    /// `FROM_START`, or the index of the cue point plus one.
+    cue:       AtomicUsize,
+    triggered: AtomicBool,
+}
+
+/// How far a `Sound` has played since it was triggered.
+#[derive(Debug)]
+struct Voice {
+    cue:    usize,
+    offset: FrameTime,
+}
+
+/// A source that can be played from its start or any of its cue points.
+#[derive(Debug)]
+pub struct Sound {
+    cues:     CuePointManager,
+    head:     Vec<f32>,
+    length:   FrameTime,
+    trigger:  Arc<Trigger>,
+    voice:    Option<Voice>,
+    player:   Player,
+    scratch:  Vec<f32>,
+}
+
+impl Sound {
+    /// Reads the cue points of the source at `path`. Returns the sound,
+    /// and what streams it once it is started.
+    fn new (path: &str) -> Result<(Sound, Renderer<Stream>), String> {
+        let cues = CuePointManager::new(path)?;
+        let head = cues.read(0)?;
+        let file = SndFile::new(path, OpenMode::Read)?;
+        let channels = cues.channels().max(1);
+        let length = file.get_sndinfo().frames.max(0) as FrameTime;
+        let stream = Stream {
+            file: RefCell::new(file),
+            channels,
+            length,
+            block: RefCell::new((0, Vec::new()))
+        };
+        let status = Arc::new(Status::default());
+        let (renderer, player) = prepare(stream, N_CUE_BUFFER_FRAMES, status);
+        let sound = Sound {
+            cues,
+            head,
+            length,
+            trigger: Arc::new(Trigger::default()),
+            voice:   None,
+            player,
+            scratch: vec![0.0; MAX_CYCLE_FRAMES]
+        };
+        Ok((sound, renderer))
+    }
+    /// Gets where `cue` starts, and the frames kept in memory after it.
+    fn from (&self, cue: usize) -> (FrameTime, &[f32]) {
+        match cue {
+            FROM_START => (0, &self.head),
+            cue => (self.cues.position(cue - 1), self.cues.buffer(cue - 1))
+        }
+    }
+    /// Adds the next `n_frames` frames to the interleaved `output`,
+    /// which has `channels` channels. Sources with fewer channels
+    /// are repeated over the rest.
+    fn process (&mut self, n_frames: FrameTime, output: &mut [f32], channels: usize) {
+        if self.trigger.triggered.swap(false, Ordering::AcqRel) {
+            let cue = self.trigger.cue.load(Ordering::Acquire);
+            self.voice = Some(Voice { cue, offset: 0 });
+        }
+        let (cue, mut offset) = match &self.voice {
+            Some(voice) => (voice.cue, voice.offset),
+            None => return
+        };
+        let source_channels = self.player.channels();
+        let (start, buffer) = self.from(cue);
+        let buffered = buffer.len() / source_channels;
+
+        // from memory, while the engine locates to where it runs out
+        let mut done = 0;
+        while done < n_frames && offset < buffered {
+            let frame = &buffer[offset * source_channels..];
+            for (channel, sample) in output[done * channels..][..channels].iter_mut().enumerate() {
+                *sample += frame[channel % source_channels];
+            }
+            done += 1;
+            offset += 1;
+        }
+        let position = start + offset;
+        if done < n_frames && position < self.length {
+            let frames = n_frames - done;
+            self.player.process_at(position, frames, true);
+            for source_channel in 0..source_channels {
+                self.player.write_channel(source_channel, &mut self.scratch[..frames]);
+                for channel in (source_channel..channels).step_by(source_channels) {
+                    for (index, sample) in self.scratch[..frames].iter().enumerate() {
+                        output[(done + index) * channels + channel] += *sample;
+                    }
+                }
+            }
+            offset += frames;
+        } else {
+            self.player.process_at(start + buffered, 0, false);
+        }
+        self.voice = if start + offset < self.length {
+            Some(Voice { cue, offset })
+        } else {
+            None
+        };
+    }
+}
+
+/// A set of named sounds, mixed together.
+pub struct Sampler {
+    sounds:    IndexMap<String, Sound>,
+    channels:  usize,
+    scratch:   Vec<f32>,
+    frames:    FrameTime,
+    /// What streams each sound, until they are started. Behind a mutex
+    /// only so that the sampler can be moved to the realtime thread;
+    /// it is never locked.
+    renderers: Mutex<Vec<Renderer<Stream>>>,
+    worker:    Option<JoinHandle<()>>,
+}
+
+impl Sampler {
+    /// Creates an empty sampler with `channels` output channels.
+    pub fn new (channels: usize) -> Sampler {
+        Sampler {
+            sounds:    IndexMap::new(),
+            channels,
+            scratch:   vec![0.0; MAX_CYCLE_FRAMES * channels],
+            frames:    0,
+            renderers: Mutex::new(Vec::new()),
+            worker:    None
+        }
+    }
+    /// Loads the source at `path` as the sound `name`.
+    /// Sounds can only be loaded before the sampler is started.
+    pub fn load (&mut self, name: &str, path: &str) -> Result<(), String> {
+        if self.worker.is_some() {
+            return Err(String::from("can not load sounds once the sampler is started"))
+        }
+        let (sound, renderer) = Sound::new(path)?;
+        self.sounds.insert(name.to_string(), sound);
+        self.renderers.get_mut().unwrap().push(renderer);
+        Ok(())
+    }
+    /// Starts streaming every sound from disk, on one worker thread,
+    /// for playback at sample `rate`.
+    pub fn start (&mut self, rate: usize) {
+        if self.worker.is_some() { return }
+        let renderers = std::mem::take(self.renderers.get_mut().unwrap());
+        self.worker = Some(spawn_all(renderers, N_CUE_BUFFER_FRAMES, rate, "lude sampler"));
+    }
+    /// Gets a handle to trigger the sounds that are loaded so far
+    /// from other threads.
+    pub fn triggers (&self) -> Triggers {
+        let sounds = self.sounds.iter().map(|(name, sound)| {
+            let labels = sound.cues.labels().map(|(label, _)| label.to_string()).collect();
+            (name.clone(), (labels, sound.trigger.clone()))
+        }).collect();
+        Triggers { sounds: Arc::new(sounds) }
+    }
+    /// Mixes the next `n_frames` frames of all sounds that are playing,
+    /// to be added to the outputs with `add_channel`.
+    pub fn process (&mut self, n_frames: FrameTime) {
+        let frames = n_frames.min(MAX_CYCLE_FRAMES);
+        let wanted = frames * self.channels;
+        for sample in self.scratch[..wanted].iter_mut() {
+            *sample = 0.0
+        }
+        for sound in self.sounds.values_mut() {
+            sound.process(frames, &mut self.scratch[..wanted], self.channels);
+        }
+        self.frames = frames;
+    }
+    /// Adds one channel of the frames mixed by `process` to `output`.
+    pub fn add_channel (&self, channel: usize, output: &mut [f32]) {
+        if channel >= self.channels { return }
+        for (index, sample) in output.iter_mut().take(self.frames).enumerate() {
+            *sample += self.scratch[index * self.channels + channel]
+        }
+    }
+}
+
+impl Drop for Sampler {
+    fn drop (&mut self) {
+        for sound in self.sounds.values() {
+            sound.player.status().end();
+        }
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl std::fmt::Debug for Sampler {
+    fn fmt (&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Sampler")
+            .field("sounds", &self.sounds)
+            .field("channels", &self.channels)
+            .finish()
+    }
+}
+
+/// Triggers the sounds of a `Sampler` from another thread.
+#[derive(Debug, Clone)]
+pub struct Triggers {
+    sounds: Arc<TriggerMap>,
+}
+
+impl Triggers {
+    /// Starts playing `sound` from the cue point `cue`,
+    /// or from its start if `cue` is `None`, in the next cycle.
+    pub fn play (&self, sound: &str, cue: Option<&str>) -> Result<(), String> {
+        let (labels, trigger) = self.sounds.get(sound)
+            .ok_or_else(|| format!("no sound named {}", sound))?;
+        let cue = match cue {
+            None => FROM_START,
+            Some(cue) => 1 + labels.iter().position(|label| label == cue)
+                .ok_or_else(|| format!("{} has no cue point named {}", sound, cue))?
+        };
+        trigger.cue.store(cue, Ordering::Release);
+        trigger.triggered.store(true, Ordering::Release);
+        Ok(())
+    }
+}
@@ -35,6 +35,7 @@
 //use std::str::from_utf8;
 use std::str::*;
 use std::ptr;
+use std::mem;
 use std::ffi::CString;
 use std::ffi::CStr;
 use std::ops::BitOr;
@@ -63,6 +64,26 @@ pub struct SndInfo {
     pub seekable : i32
 }
 
+/// A named point in the music.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CuePoint {
+    pub name : String,
+    pub position : i64
+}
+
+/// How many cue points a file can have.
+pub const MAX_CUES : usize = ffi::SF_MAX_CUES;
+
+const EMPTY_CUE : ffi::CuePoint = ffi::CuePoint {
+    indx : 0,
+    position : 0,
+    fcc_chunk : 0,
+    chunk_start : 0,
+    block_start : 0,
+    sample_offset : 0,
+    name : [0; 256]
+};
+
 /// Modes availables for the open function.
 ///
 /// * Read - Read only mode
@@ -242,11 +263,10 @@ pub struct SndFile {
     info : Box<SndInfo>
 }
 
-impl Clone for SndFile {
-    fn clone(&self) -> SndFile {
-        SndFile {
-            handle : self.handle,
-            info : self.info.clone()
+impl Drop for SndFile {
+    fn drop(&mut self) {
+        if self.handle != 0 {
+            unsafe { ffi::sf_close(self.handle); }
         }
     }
 }
@@ -398,12 +418,71 @@ impl SndFile {
     pub fn set_string(&mut self,
                       string_type : StringSoundType,
                       string : String) -> Error {
-  		//let c_string = CString::new(string).unwrap();
+        let c_string = CString::new(string).unwrap();
         unsafe {
             ffi::sf_set_string(self.handle,
                                string_type as i32,
-                               string.as_ptr() as *mut _)
+                               c_string.as_ptr() as *mut _)
+        }
+    }
+
+    /**
+     * Retrieve the cue points of the music, in the order they are stored.
+     * Cue points without a label are named after their index.
+     */
+    pub fn get_cues(&self) -> Vec<CuePoint> {
+        let mut cues = Box::new(ffi::Cues {
+            cue_count : 0,
+            cue_points : [EMPTY_CUE; ffi::SF_MAX_CUES]
+        });
+        let found = unsafe {
+            ffi::sf_command(self.handle, ffi::SFC_GET_CUE,
+                            &mut *cues as *mut ffi::Cues as *mut _,
+                            mem::size_of::<ffi::Cues>() as i32)
+        };
+        if found != ffi::SF_TRUE { return Vec::new() }
+        let count = (cues.cue_count as usize).min(ffi::SF_MAX_CUES);
+        cues.cue_points[..count].iter().map(|cue| {
+            let name = unsafe { CStr::from_ptr(cue.name.as_ptr()) }
+                .to_string_lossy().to_string();
+            CuePoint {
+                name: if name.is_empty() { cue.indx.to_string() } else { name },
+                position: cue.sample_offset as i64
+            }
+        }).collect()
+    }
+
+    /**
+     * Set the cue points of the music. Only has an effect before
+     * anything is written, and only for formats which have cue points.
+     * There can be at most `MAX_CUES`.
+     *
+     * Return Ok() on success, a string representation of the error otherwise.
+     */
+    pub fn set_cues(&mut self, points : &[CuePoint]) -> Result<(), String> {
+        if points.len() > MAX_CUES {
+            return Err(format!("can not set {} cue points, only {}", points.len(), MAX_CUES))
+        }
+        let mut cues = Box::new(ffi::Cues {
+            cue_count : points.len() as u32,
+            cue_points : [EMPTY_CUE; ffi::SF_MAX_CUES]
+        });
+        for (i, point) in points.iter().enumerate() {
+            let cue = &mut cues.cue_points[i];
+            cue.indx = i as i32 + 1;
+            cue.position = point.position as u32;
+            cue.fcc_chunk = i32::from_le_bytes(*b"data");
+            cue.sample_offset = point.position as u32;
+            for (c, byte) in cue.name.iter_mut().zip(point.name.bytes().take(255)) {
+                *c = byte as _;
+            }
         }
+        let done = unsafe {
+            ffi::sf_command(self.handle, ffi::SFC_SET_CUE,
+                            &mut *cues as *mut ffi::Cues as *mut _,
+                            mem::size_of::<ffi::Cues>() as i32)
+        };
+        if done == ffi::SF_TRUE { Ok(()) } else { Err(String::from("could not set cue points")) }
     }
 
     /**
@@ -426,15 +505,17 @@ impl SndFile {
     /**
      * Close the SndFile object.
      *
-     * This function must be called before the exist of the program to destroy
-     * all the resources.
+     * The file is also closed when the SndFile is dropped; this function
+     * only tells whether closing it succeeded.
      *
      * Return NoError if destruction success, an other error code otherwise.
      */
-    pub fn close(&self) -> Error {
-        unsafe {
+    pub fn close(mut self) -> Error {
+        let error = unsafe {
             ffi::sf_close(self.handle)
-        }
+        };
+        self.handle = 0;
+        error
     }
 
     /**
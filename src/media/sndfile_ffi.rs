@@ -121,6 +121,33 @@ pub const SF_FORMAT_SUBMASK : FORMAT_TYPE      = 0x0000FFFF;
 pub const SF_FORMAT_TYPEMASK : FORMAT_TYPE     = 0x0FFF0000;
 pub const SF_FORMAT_ENDMASK : FORMAT_TYPE      = 0x30000000;
 
+/* Commands for sf_command. */
+
+pub const SFC_GET_CUE_COUNT : i32              = 0x10CD;
+pub const SFC_GET_CUE : i32                    = 0x10CE;
+pub const SFC_SET_CUE : i32                    = 0x10CF;
+
+/// Maximum number of cue points in SF_CUES.
+pub const SF_MAX_CUES : usize                  = 100;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CuePoint {
+    pub indx : i32,
+    pub position : u32,
+    pub fcc_chunk : i32,
+    pub chunk_start : i32,
+    pub block_start : i32,
+    pub sample_offset : u32,
+    pub name : [c_char; 256]
+}
+
+#[repr(C)]
+pub struct Cues {
+    pub cue_count : u32,
+    pub cue_points : [CuePoint; SF_MAX_CUES]
+}
+
 pub type SNDFILE = c_void;
 pub type SNDFILEhandle = intptr_t;
 
@@ -137,7 +164,7 @@ extern "C" {
     pub fn sf_format_check(info : *mut SndInfo) -> SF_BOOL;
 
     pub fn sf_seek(sndfile : SNDFILEhandle, frames : i64, whence : i32) -> i64;
-    pub fn sf_command(sndfile : SNDFILEhandle, cmd : i32, data : *mut c_void, datasize : i32) -> Error;
+    pub fn sf_command(sndfile : SNDFILEhandle, cmd : i32, data : *mut c_void, datasize : i32) -> i32;
 
     pub fn sf_error(sndfile : SNDFILEhandle) -> Error;
     pub fn sf_strerror(sndfile : SNDFILEhandle) -> *mut c_char;
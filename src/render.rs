@@ -1,10 +1,10 @@
 use std::time::Instant;
-use std::cell::RefCell;
 use crate::document::Document;
 use crate::types::{FrameTime, Frame, Chunk, Wave};
 
 /// Generates and returns the `Chunk` of `doc`
-/// that is between `begin` and `end`.
+/// that is between `begin` and `end` in playback time,
+/// i.e. with the document's loops unrolled.
 /// ```
 /// let doc = Document::new()
 /// assert!(render(doc, 0, 999).len(), 1000)
@@ -36,7 +36,7 @@ pub fn render (doc: &Document, begin: FrameTime, end: FrameTime) -> Chunk {
     let mut frames = Vec::new();
 
     for index in begin..(end+1) {
-        let frame = doc.get_frame(index);
+        let frame = doc.get_frame(doc.unloop(index));
         frames.push(frame);
     }
 
@@ -46,46 +46,18 @@ pub fn render (doc: &Document, begin: FrameTime, end: FrameTime) -> Chunk {
     frames
 }
 
-/// Converts a `Chunk` of optional multi-channel `Frame`s to an array of `Wave`s.
-pub fn to_channels (chunk: Chunk) -> Vec<Wave> {
+/// Converts a `Chunk` of optional multi-channel `Frame`s to an array of
+/// `channels` `Wave`s. Channels that a frame does not have are silent.
+pub fn to_channels (chunk: Chunk, channels: usize) -> Vec<Wave> {
     let start = Instant::now();
 
-    let mut channels: Vec<RefCell<Wave>> = Vec::new();
-    for (frame_index, frame) in chunk.iter().enumerate() {
-        match frame {
-            Some(frame) => {
-                for (
-                    channel_index,
-                    channel_value
-                ) in frame.iter().enumerate() {
-                    match channels.get(channel_index) {
-                        Some(channel) => {
-                            let mut channel = channel.borrow_mut();
-                            channel.push(*channel_value);
-                        },
-                        None => {
-                            let mut new_channel =
-                                Wave::with_capacity(chunk.len());
-                            for _ in 0..frame_index {
-                                new_channel.push(0);
-                            }
-                            new_channel.push(*channel_value);
-                            channels.push(RefCell::new(new_channel));
-                        }
-                    }
-                }
-            },
-            None => {
-                for channel in channels.iter_mut() {
-                    channel.borrow_mut().push(0);
-                }
-            },
+    let mut output = vec![Wave::with_capacity(chunk.len()); channels];
+    for frame in chunk.iter() {
+        for (channel_index, channel) in output.iter_mut().enumerate() {
+            let value = frame.as_ref().and_then(|frame| frame.get(channel_index));
+            channel.push(value.cloned().unwrap_or(0));
         }
     }
-    let mut output = Vec::new();
-    for channel in channels {
-        output.push(channel.into_inner());
-    }
 
     eprintln!("regrouped chunk of {} frames into {} channels in {} usec",
         &chunk.len(), &output.len(), start.elapsed().as_micros());
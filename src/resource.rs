@@ -0,0 +1,145 @@
+//! registry of everything a document is made from
+//!
+//! Every source file that a document uses is registered once,
+//! under an `Identifier` which stays the same when the resource
+//! is relinked to another file. Events refer to resources by
+//! their identifier.
+
+use std::fmt;
+use std::fs::read;
+use std::path::Path;
+use crate::types::FrameTime;
+use crate::media::SoundMap;
+
+/// Stable identifier of a resource within its `Registry`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Identifier(pub usize);
+
+impl fmt::Display for Identifier {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Something a document is made from, and what is known about it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resource {
+    /// Human-readable name, unique within the registry.
+    pub name:     String,
+    /// Path to the file, if there is one.
+    pub path:     Option<String>,
+    /// Frames per second.
+    pub rate:     usize,
+    /// Number of channels.
+    pub channels: usize,
+    /// Number of frames.
+    pub length:   FrameTime,
+}
+
+impl Resource {
+    /// Reads the file and hashes its contents, to tell if it has changed.
+    /// This is only done when asked for, as media files can be large.
+    pub fn hash (&self) -> Option<u64> {
+        self.path.as_ref().and_then(|path| read(path).ok()).map(|data| hash(&data))
+    }
+}
+
+/// All resources used by a document.
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    resources: Vec<Resource>,
+}
+
+impl Registry {
+    /// Creates an empty registry.
+    pub fn new () -> Registry {
+        Registry { resources: Vec::new() }
+    }
+    /// Registers the media file at `path`, reading its metadata
+    /// through `media`, unless it is already registered.
+    pub fn register_file (&mut self, path: &str, media: &SoundMap) -> Identifier {
+        if let Some(id) = self.find_path(path) { return id }
+        let name = self.unique_name(&default_name(path));
+        let mut resource = Resource {
+            name,
+            path:     None,
+            rate:     0,
+            channels: 0,
+            length:   0
+        };
+        link(&mut resource, path, media);
+        self.register(resource)
+    }
+    /// Registers `resource` as it is.
+    pub fn register (&mut self, resource: Resource) -> Identifier {
+        self.resources.push(resource);
+        Identifier(self.resources.len() - 1)
+    }
+    /// Gets the resource with identifier `id`.
+    pub fn get (&self, id: Identifier) -> &Resource {
+        &self.resources[id.0]
+    }
+    /// Gets the path of the resource with identifier `id`, or
+    /// an empty string if it has none.
+    pub fn path (&self, id: Identifier) -> &str {
+        self.get(id).path.as_deref().unwrap_or("")
+    }
+    /// Lists all resources, in the order they were registered.
+    pub fn list (&self) -> impl Iterator<Item = (Identifier, &Resource)> {
+        self.resources.iter().enumerate().map(|(i, resource)| (Identifier(i), resource))
+    }
+    /// Finds a resource by name.
+    pub fn find (&self, name: &str) -> Option<Identifier> {
+        self.resources.iter().position(|r| r.name == name).map(Identifier)
+    }
+    /// Finds a resource by path.
+    pub fn find_path (&self, path: &str) -> Option<Identifier> {
+        self.resources.iter().position(|r| r.path.as_deref() == Some(path)).map(Identifier)
+    }
+    /// Points the resource with identifier `id` to the file at `path`,
+    /// and reads its metadata again through `media`.
+    pub fn relink (&mut self, id: Identifier, path: &str, media: &SoundMap) -> Result<(), String> {
+        if !Path::new(path).is_file() { return Err(format!("{} is not a file", path)) }
+        if let Some(other) = self.find_path(path) {
+            if other != id { return Err(format!("{} is already linked to {}", path, other)) }
+        }
+        link(&mut self.resources[id.0], path, media);
+        Ok(())
+    }
+    fn unique_name (&self, name: &str) -> String {
+        let mut unique = name.to_string();
+        let mut count = 1;
+        while self.find(&unique).is_some() {
+            count += 1;
+            unique = format!("{}_{}", name, count);
+        }
+        unique
+    }
+}
+
+/// Points `resource` to the file at `path` and reads its metadata.
+fn link (resource: &mut Resource, path: &str, media: &SoundMap) {
+    let (rate, channels, length) = media.info(path);
+    resource.path     = Some(path.to_string());
+    resource.rate     = rate;
+    resource.channels = channels;
+    resource.length   = length;
+}
+
+/// Gets the name of the file at `path`, without extension.
+fn default_name (path: &str) -> String {
+    Path::new(path).file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string())
+}
+
+/// Hashes `data` with 64-bit FNV-1a, which does not change
+/// between versions of Lude or Rust.
+pub fn hash (data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
@@ -1,22 +1,89 @@
-use crate::eval::{read, eval};
+use crate::eval::{read, eval, relink};
 use crate::render::{render, to_channels, to_frames};
-use crate::document::Document;
+use crate::document::{Document, Tempo};
 use crate::types::{FrameTime, Chunk};
+use crate::timeline::{Span, Timeline};
+use crate::resource::{hash, Identifier};
+use crate::media::SoundMap;
+use crate::io::file::{cue_points, read_provenance, write_to_file, Provenance, WriteOptions};
+use crate::media::cues::N_CUE_BUFFER_FRAMES;
+use crate::media::onsets;
+use crate::media::sampler::Sampler;
+use crate::media::sndfile::{
+    CuePoint, FormatType, OpenMode, SndFile, SndInfo, StringSoundType, MAX_CUES
+};
+use crate::io::ring::ring;
+use crate::io::engine::{Backend, Playable, Quantize, Status, Swap, prepare};
+use crate::io::osc::{OscArg, OscMessage};
+use crate::io::midi::{export, import, NoteMap};
+use crate::io::udp_osc::{emit, Overrides, Server};
+use std::net::UdpSocket;
+use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::channel;
+use crate::io::dummy::DummyBackend;
 
-fn assert_some<T> (v: &Vec<Option<T>>, i: usize) {
+fn assert_some<T> (v: &[Option<T>], i: usize) {
     match v.get(i).unwrap() {
         Some(_) => {},
         None => panic!("#{} should exist", &i),
     }
 }
 
-fn assert_none<T: std::fmt::Debug> (v: &Vec<Option<T>>, i: usize) {
-    match v.get(i).unwrap() {
-        Some(x) => panic!("#{} should not exist, was {:?}", &i, &x),
-        None => {}
+fn assert_none<T: std::fmt::Debug> (v: &[Option<T>], i: usize) {
+    if let Some(x) = v.get(i).unwrap() {
+        panic!("#{} should not exist, was {:?}", &i, &x)
     }
 }
 
+/// Writes a mono file named `name` to the temporary directory,
+/// in which every frame is its own index modulo 30000, and returns its path.
+fn write_ramp (name: &str, length: usize, cues: &[CuePoint]) -> String {
+    let mut ramp: Vec<i16> = (0..length).map(|i| (i % 30000) as i16).collect();
+    write_samples(name, &mut ramp, cues)
+}
+
+/// Writes `samples` as a mono file named `name` to the temporary directory,
+/// and returns its path.
+fn write_samples (name: &str, samples: &mut [i16], cues: &[CuePoint]) -> String {
+    let length = samples.len();
+    let path = std::env::temp_dir().join(name).to_str().unwrap().to_string();
+    let mut file = SndFile::new_with_info(&path, OpenMode::Write, Box::new(SndInfo {
+        frames: length as i64,
+        samplerate: 44100,
+        channels: 1,
+        format: FormatType::FormatWav as i32 | FormatType::FormatPcm16 as i32,
+        sections: 0,
+        seekable: 0
+    })).unwrap();
+    if !cues.is_empty() { file.set_cues(cues).unwrap() }
+    file.writef_i16(samples, length as i64);
+    file.close();
+    path
+}
+
+/// Renders `doc` and returns every frame of its first channel,
+/// up to where its events end.
+fn render_mono (doc: &Document) -> Vec<i16> {
+    let end = doc.events.iter().map(|(start, event)| start + event.span()).max().unwrap_or(0);
+    if end == 0 { return Vec::new() }
+    render(doc, 0, end - 1).into_iter()
+        .map(|frame| frame.map(|frame| frame[0]).unwrap_or(0))
+        .collect()
+}
+
+/// Evaluates `source`, in which `SRC` stands for the path to `media`,
+/// and returns every frame of its first channel, up to where its events end.
+fn frames (media: &str, source: &str) -> Vec<i16> {
+    render_mono(&eval(read(&source.replace("SRC", media))))
+}
+
+/// Frames `start` up to `end` of a file written by `write_ramp`.
+fn ramp (start: i16, end: i16) -> Vec<i16> {
+    (start..end).collect()
+}
+
 fn eval_expect_len (src: &str, len: FrameTime, elen: usize) -> Document {
     let doc = eval(read(src));
     assert_eq!(doc.length, len);
@@ -63,10 +130,10 @@ fn test_1_source_slice () {
 
 #[test]
 fn test_2_jumps () {
-    for (i, (src, events, samples, ranges)) in vec![
+    for (i, (src, samples, ranges)) in vec![
         (
             "@10 ./test/100ms.wav|:10| @30 ./test/100ms.wav|:10|:10|",
-            0, 50, vec![
+            50, vec![
                 (true,  0, 0),
                 (false, 1, 1)
             ]
@@ -89,3 +156,829 @@ fn expect_range (out: &Chunk, exists: bool, start: FrameTime, end: FrameTime) {
         }
     }
 }
+
+/// Plays back frame `i` as the value `i * gain` in every channel.
+struct Ramp {
+    len:      FrameTime,
+    channels: usize,
+    gain:     f32
+}
+
+impl Playable for Ramp {
+    fn channels (&self) -> usize { self.channels }
+    fn len (&self) -> FrameTime { self.len }
+    fn frame (&self, index: FrameTime, frame: &mut [f32]) {
+        for sample in frame.iter_mut() { *sample = index as f32 * self.gain }
+    }
+}
+
+#[test]
+fn test_3_ring () {
+    let (mut producer, mut consumer) = ring(4);
+    assert_eq!(producer.push(&[1.0, 2.0, 3.0]), 3);
+    assert_eq!(producer.push(&[4.0, 5.0]), 1);
+    let mut out = [0.0; 3];
+    assert_eq!(consumer.pop(&mut out), 3);
+    assert_eq!(out, [1.0, 2.0, 3.0]);
+    assert_eq!(producer.push(&[5.0, 6.0]), 2);
+    assert_eq!(consumer.skip(1), 1);
+    assert_eq!(consumer.pop(&mut out), 2);
+    assert_eq!(out[..2], [5.0, 6.0]);
+    assert!(consumer.is_empty());
+}
+
+#[test]
+fn test_4_playback_schedule () {
+    let (mut renderer, mut player) = prepare(Ramp { len: 100, channels: 2, gain: 1.0 }, 64, Arc::default());
+    let mut backend = DummyBackend::new(44100, 16);
+    // the renderer never gets more than `latency` frames ahead
+    assert_eq!(renderer.fill(), 64);
+    assert_eq!(renderer.fill(), 0);
+    for _ in 0..4 { backend.cycle(&mut player) }
+    assert_eq!(renderer.fill(), 36);
+    backend.run(player);
+    assert_eq!(backend.cycles, 7);
+    for channel in backend.output.iter() {
+        for (index, sample) in channel.iter().enumerate().take(100) {
+            assert_eq!(*sample, index as f32);
+        }
+    }
+}
+
+#[test]
+fn test_5_playback_underrun () {
+    let (mut renderer, mut player) = prepare(Ramp { len: 64, channels: 1, gain: 1.0 }, 64, Arc::default());
+    let mut backend = DummyBackend::new(44100, 16);
+    // nothing rendered yet: silence, and the missed frames are skipped
+    backend.cycle(&mut player);
+    assert_eq!(player.status().underruns(), 1);
+    assert!(backend.output[0].iter().all(|sample| *sample == 0.0));
+    renderer.fill();
+    backend.run(player);
+    for (index, sample) in backend.output[0].iter().enumerate().skip(16) {
+        assert_eq!(*sample, if index < 64 { index as f32 } else { 0.0 });
+    }
+}
+
+#[test]
+fn test_6_playback_transport () {
+    let (mut renderer, mut player) = prepare(Ramp { len: 1000, channels: 1, gain: 1.0 }, 64, Arc::default());
+    let mut buffer = vec![0.0; 16];
+    renderer.fill();
+    // stopped: silent, nothing is consumed
+    player.process_at(0, 16, false);
+    player.write_channel(0, &mut buffer);
+    assert!(buffer.iter().all(|sample| *sample == 0.0));
+    player.process_at(0, 16, true);
+    player.write_channel(0, &mut buffer);
+    assert_eq!(buffer[0], 0.0);
+    assert_eq!(buffer[15], 15.0);
+    // locate: silent until the renderer has moved
+    player.process_at(500, 16, true);
+    player.write_channel(0, &mut buffer);
+    assert!(buffer.iter().all(|sample| *sample == 0.0));
+    renderer.fill();
+    player.process_at(516, 16, true);
+    player.write_channel(0, &mut buffer);
+    assert_eq!(buffer[0], 516.0);
+    assert_eq!(player.status().underruns(), 0);
+}
+
+#[test]
+fn test_7_tempo () {
+    let doc = eval(read("!bpm 90 !meter 3"));
+    let tempo = doc.tempo.unwrap();
+    assert_eq!(tempo, Tempo { bpm: 90.0, beats_per_bar: 3 });
+    assert_eq!(tempo.bbt(0, 44100), (1, 1, 0));
+    // 90 bpm is 29400 frames per beat at 44100Hz
+    assert_eq!(tempo.bbt(29400 * 4 + 14700, 44100), (2, 2, 960));
+}
+
+#[test]
+fn test_8_playback_swap () {
+    let (mut renderer, mut player) = prepare(Ramp { len: 1000, channels: 1, gain: 1.0 }, 64, Arc::default());
+    let (swaps, receiver) = channel();
+    renderer.swap_from(receiver);
+    let mut backend = DummyBackend::new(44100, 16);
+    renderer.fill();
+    swaps.send(Swap {
+        playable: Ramp { len: 1000, channels: 1, gain: -1.0 },
+        quantize: Quantize::Block
+    }).unwrap();
+    // what is already rendered plays out, then the new version takes over
+    for _ in 0..5 {
+        backend.cycle(&mut player);
+        renderer.fill();
+    }
+    assert_eq!(backend.output[0][63], 63.0);
+    assert_eq!(backend.output[0][64], -64.0);
+    assert_eq!(player.status().underruns(), 0);
+}
+
+#[test]
+fn test_9_safe_point () {
+    let doc = eval(read("!bpm 120 @100 #1 @300 #2"));
+    assert_eq!(doc.safe_point(150, Quantize::Block, 44100), 150);
+    assert_eq!(doc.safe_point(150, Quantize::Marker, 44100), 300);
+    assert_eq!(doc.safe_point(400, Quantize::Marker, 44100), 400);
+    // 4 beats of 22050 frames, or of 24000 at 48 kHz
+    assert_eq!(doc.safe_point(150, Quantize::Bar, 44100), 88200);
+    assert_eq!(doc.safe_point(150, Quantize::Bar, 48000), 96000);
+    // markers are where they are heard, after loops
+    let doc = eval(read("./test/100ms.wav { |:10| }*3 #1 @5 #2"));
+    assert_eq!(doc.safe_point(0, Quantize::Marker, 44100), 5);
+    assert_eq!(doc.safe_point(6, Quantize::Marker, 44100), 15);
+    assert_eq!(doc.safe_point(26, Quantize::Marker, 44100), 30);
+}
+
+#[test]
+fn test_10_loops () {
+    let looped = eval(read(
+        "./test/100ms.wav |:10| { |100:110| }*3 |200:210|"
+    ));
+    assert_eq!(looped.duration(), Some(50));
+    assert_eq!(looped.unloop(15), 15);
+    assert_eq!(looped.unloop(25), 15);
+    assert_eq!(looped.unloop(35), 15);
+    assert_eq!(looped.unloop(45), 25);
+    assert_eq!(looped.replay_from(15, 0), Some(15));
+    assert_eq!(looped.replay_from(15, 16), Some(25));
+    assert_eq!(looped.replay_from(15, 36), None);
+    assert_eq!(looped.replay_from(25, 0), Some(45));
+    let unrolled = eval(read(
+        "./test/100ms.wav |:10|100:110|100:110|100:110|200:210|"
+    ));
+    let looped = render(&looped, 0, 50);
+    let unrolled = render(&unrolled, 0, 50);
+    assert_eq!(looped, unrolled);
+
+    let endless = eval(read("./test/100ms.wav { |:10| }* |200:210|"));
+    assert_eq!(endless.duration(), None);
+    assert_eq!(endless.unloop(12345), 5);
+    assert_eq!(endless.replay_from(5, 12340), Some(12345));
+    assert_eq!(endless.replay_from(15, 0), None);
+    let twice = eval(read("./test/100ms.wav { |:10| } |200:210|"));
+    assert_eq!(twice.duration(), Some(30));
+}
+
+#[test]
+fn test_11_osc () {
+    let message = OscMessage::new("/lude/set", vec![
+        OscArg::Str("bpm".into()), OscArg::Float(90.0), OscArg::Int(-1)
+    ]);
+    let packet = message.encode();
+    assert_eq!(&packet[..12], b"/lude/set\0\0\0");
+    assert_eq!(&packet[12..20], b",sfi\0\0\0\0");
+    assert_eq!(packet.len(), 32);
+    assert_eq!(OscMessage::decode(&packet), Ok(message));
+    assert!(OscMessage::decode(b"/lude/locate\0\0\0\0,i\0\0\0\0").is_err());
+}
+
+#[test]
+fn test_12_osc_server () {
+    let status = Arc::new(Status::default());
+    let (_, mut player) = prepare(Ramp { len: 1000, channels: 1, gain: 1.0 }, 64, status.clone());
+    let (swaps, swapped) = channel();
+    let overrides = Arc::new(Mutex::new(Overrides::default()));
+    let latest = Arc::new(Mutex::new(Document::new()));
+    let broken = Arc::new(AtomicBool::new(false));
+    let load = {
+        let overrides = overrides.clone();
+        let latest = latest.clone();
+        let broken = broken.clone();
+        move || {
+            let source = if broken.load(Ordering::SeqCst) {
+                "./test/missing.wav |:10|"
+            } else {
+                "./test/100ms.wav #1 { |:10| } @100 #2"
+            };
+            let mut doc = eval(read(source));
+            overrides.lock().unwrap().apply(&mut doc);
+            *latest.lock().unwrap() = doc.clone();
+            doc
+        }
+    };
+    let mut server = Server::new(status.clone(), swaps, overrides, load, latest);
+    let send = |server: &mut Server, addr: &str, args: Vec<OscArg>| {
+        server.handle(&OscMessage::new(addr, args))
+    };
+
+    let reply = send(&mut server, "/lude/stop", vec![]);
+    assert_eq!(reply.addr, "/lude/status");
+    assert_eq!(reply.args[1], OscArg::Int(0));
+    send(&mut server, "/lude/locate", vec![OscArg::Int(100)]);
+    player.process(64);
+    player.process(64);
+    assert_eq!(status.position(), 100);
+
+    let reply = send(&mut server, "/lude/marker", vec![OscArg::Str("2".into())]);
+    assert_eq!(reply.addr, "/lude/error");
+    send(&mut server, "/lude/reload", vec![]);
+    assert!(swapped.try_recv().is_ok());
+    // the marker is heard after the loop has played twice
+    send(&mut server, "/lude/marker", vec![OscArg::Str("2".into())]);
+    send(&mut server, "/lude/start", vec![]);
+    player.process(64);
+    assert_eq!(status.position(), 174);
+    assert!(status.rolling());
+
+    send(&mut server, "/lude/mute", vec![OscArg::Str("./test/100ms.wav".into())]);
+    let doc = swapped.try_recv().unwrap().playable;
+    assert_eq!(doc.get_frame(0), None);
+    send(&mut server, "/lude/mute", vec![OscArg::Str("./test/100ms.wav".into()), OscArg::Int(0)]);
+    send(&mut server, "/lude/set", vec![OscArg::Str("bpm".into()), OscArg::Int(90)]);
+    let doc = swapped.try_iter().last().unwrap().playable;
+    assert!(doc.get_frame(0).is_some());
+    assert_eq!(doc.tempo.unwrap().bpm, 90.0);
+    let reply = send(&mut server, "/lude/set", vec![OscArg::Str("bpm".into()), OscArg::Int(0)]);
+    assert_eq!(reply.addr, "/lude/error");
+    // settings that are only read while evaluating are refused
+    let reply = send(&mut server, "/lude/set", vec![OscArg::Str("snap".into()), OscArg::Int(10)]);
+    assert_eq!(reply.addr, "/lude/error");
+    assert!(swapped.try_recv().is_err());
+
+    // mute keeps working on the last good version when the source file breaks
+    broken.store(true, Ordering::SeqCst);
+    let reply = send(&mut server, "/lude/reload", vec![]);
+    assert_eq!(reply.addr, "/lude/error");
+    let reply = send(&mut server, "/lude/mute", vec![OscArg::Str("./test/100ms.wav".into())]);
+    assert_eq!(reply.addr, "/lude/status");
+    let doc = swapped.try_recv().unwrap().playable;
+    assert_eq!((doc.get_frame(0), doc.tempo.unwrap().bpm), (None, 90.0));
+}
+
+#[test]
+fn test_13_osc_events () {
+    let doc = eval(read("Kick=/sounds/kick/playing @100 Kick 1 0.5 +10 Kick"));
+    assert_eq!(doc.length, 110);
+    assert_eq!(doc.duration(), Some(110));
+    assert_eq!(doc.messages.get(100), Some(&vec![OscMessage::new(
+        "/sounds/kick/playing", vec![OscArg::Int(1), OscArg::Float(0.5)]
+    )]));
+    assert_eq!(doc.messages.get(110).unwrap()[0].args, vec![]);
+
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let target = receiver.local_addr().unwrap().to_string();
+    let status = Arc::new(Status::default());
+    let (mut renderer, mut player) = prepare(doc, 256, status.clone());
+    renderer.cue_to(emit(&target, status.clone()));
+    renderer.fill();
+    player.process(64);
+    let mut packet = [0; 256];
+    receiver.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+    assert!(receiver.recv(&mut packet).is_err());
+    receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    player.process(64);
+    let size = receiver.recv(&mut packet).unwrap();
+    let message = OscMessage::decode(&packet[..size]).unwrap();
+    assert_eq!(message.args[0], OscArg::Int(1));
+    let size = receiver.recv(&mut packet).unwrap();
+    let message = OscMessage::decode(&packet[..size]).unwrap();
+    assert_eq!(message.args, vec![]);
+}
+
+#[test]
+fn test_14_midi_export () {
+    // two beats at 120bpm, the second one twice
+    let doc = eval(read("!bpm 120 ./test/100ms.wav |:2205| @22050 { |:2205| }"));
+    assert_eq!(doc.replay(22050), Some(vec![22050, 24255]));
+    let mut notes = NoteMap::parse("(drums)\n\n38 ./test/other.wav\n").unwrap();
+    assert!(NoteMap::parse("128 ./test/100ms.wav").is_err());
+    let midi = export(&doc, &notes).unwrap();
+    assert_eq!(&midi[..14], b"MThd\0\0\0\x06\0\x01\0\x02\x01\xe0");
+    // tempo and meter
+    assert_eq!(&midi[14..41], &[
+        b'M', b'T', b'r', b'k', 0, 0, 0, 19,
+        0, 0xFF, 0x51, 3, 0x07, 0xA1, 0x20,
+        0, 0xFF, 0x58, 4, 4, 2, 24, 8,
+        0, 0xFF, 0x2F, 0][..]);
+    // first source not in the mapping gets the first free note
+    assert_eq!(notes.note_or_insert("./test/100ms.wav"), Ok(36));
+    let name = b"./test/100ms.wav";
+    assert_eq!(&midi[49..53], &[0, 0xFF, 0x03, name.len() as u8]);
+    assert_eq!(&midi[53..53 + name.len()], name);
+    // 48 ticks long, a beat (480 ticks) apart
+    assert_eq!(&midi[53 + name.len()..], &[
+        0, 0x99, 36, 100, 48, 0x89, 36, 0,
+        0x83, 0x30, 0x99, 36, 100, 48, 0x89, 36, 0,
+        0, 0x99, 36, 100, 48, 0x89, 36, 0,
+        0, 0xFF, 0x2F, 0][..]);
+}
+
+#[test]
+fn test_15_midi_import () {
+    let doc = eval(read("!bpm 90 !meter 3 ./test/100ms.wav |:2205| @29400 { |:2940| }"));
+    let notes = NoteMap::parse("36 ./test/100ms.wav |100:110|").unwrap();
+    let source = import(&export(&doc, &notes).unwrap(), &notes).unwrap();
+    assert_eq!(source, "!bpm 90 !meter 3\n\
+        @0 ./test/100ms.wav |100:110|\n\
+        @29400 |100:110|\n\
+        @32340 |100:110|\n");
+    let imported = eval(read(&source));
+    let starts: Vec<_> = imported.events.iter().map(|(start, _)| start).collect();
+    assert_eq!(starts, vec![0, 29400, 32340]);
+    assert!(import(&export(&doc, &NoteMap::default()).unwrap(), &NoteMap::default())
+        .unwrap().lines().count() == 1);
+    assert!(import(b"RIFF", &notes).is_err());
+    // delta times that add up to more ticks than there are
+    let mut track = Vec::new();
+    for _ in 0..17 { track.extend_from_slice(&[0xFF, 0xFF, 0xFF, 0x7F, 0xFF, 0x01, 0x00]) }
+    let mut midi = b"MThd\0\0\0\x06\0\0\0\x01\x01\xE0MTrk".to_vec();
+    midi.extend_from_slice(&(track.len() as u32).to_be_bytes());
+    midi.extend(track);
+    assert_eq!(import(&midi, &notes), Err(String::from("truncated track")));
+}
+
+/// A named timeline event that lasts for a number of frames.
+#[derive(Debug, PartialEq)]
+struct Note (char, FrameTime);
+
+impl Span for Note {
+    fn span (&self) -> FrameTime { self.1 }
+}
+
+#[test]
+fn test_16_timeline () {
+    let mut timeline = Timeline::new();
+    timeline.add(0, Note('a', 10));
+    timeline.add(5, Note('b', 2));
+    timeline.add(5, Note('c', 0));
+    timeline.add(20, Note('d', 1));
+    assert_eq!(timeline.len(), 4);
+    assert_eq!(timeline.first(), Some(0));
+    assert_eq!(timeline.last(), Some(20));
+    assert_eq!(timeline.longest(), 10);
+    let names = |events: Vec<(FrameTime, &Note)>| -> String {
+        events.iter().map(|(_, note)| note.0).collect()
+    };
+    assert_eq!(names(timeline.range(1..=20).collect()), "bcd");
+    assert_eq!(names(timeline.overlapping(5).collect()), "abc");
+    assert_eq!(names(timeline.overlapping(6).collect()), "ab");
+    assert_eq!(names(timeline.overlapping(10).collect()), "");
+    assert_eq!(timeline.move_to(5, 20, |note| note.0 == 'b'), 1);
+    assert_eq!(names(timeline.iter().collect()), "acdb");
+    assert_eq!(timeline.remove(20, |note| note.0 == 'd'), vec![Note('d', 1)]);
+    assert_eq!(timeline.drain_before(20), vec![(0, Note('a', 10)), (5, Note('c', 0))]);
+    assert_eq!(names(timeline.iter().collect()), "b");
+    assert_eq!(timeline.len(), 1);
+}
+
+#[test]
+fn test_17_resources () {
+    let mut doc = eval(read(
+        "./test/100ms.wav |:10| ./test/100ms_inverted.wav |:10| ./test/100ms.wav |:10|"
+    ));
+    let ids: Vec<_> = doc.events.iter().map(|(_, event)| event.src()).collect();
+    assert_eq!(ids, vec![Identifier(0), Identifier(1), Identifier(0)]);
+    let resources = &mut doc.resources;
+    assert_eq!(resources.list().count(), 2);
+    let kick = resources.get(Identifier(0)).clone();
+    assert_eq!(kick.name, "100ms");
+    assert_eq!(kick.path.as_deref(), Some("./test/100ms.wav"));
+    assert_eq!((kick.rate, kick.channels, kick.length), (44100, 1, 4410));
+    let data = std::fs::read("./test/100ms.wav").unwrap();
+    assert_eq!(kick.hash(), Some(hash(&data)));
+    assert_eq!(resources.find("100ms_inverted"), Some(Identifier(1)));
+    assert!(resources.relink(Identifier(0), "./test/missing.wav", &SoundMap::new()).is_err());
+    assert!(resources.relink(Identifier(0), "./test/100ms_inverted.wav", &SoundMap::new()).is_err());
+
+    let copy = std::env::temp_dir().join("lude_test_17.wav");
+    std::fs::write(&copy, &data).unwrap();
+    let copy = copy.to_str().unwrap();
+    doc.relink(Identifier(0), copy).unwrap();
+    assert_eq!(doc.resources.path(Identifier(0)), copy);
+    assert_eq!(doc.resources.get(Identifier(0)).name, "100ms");
+    assert_eq!(doc.resources.get(Identifier(0)).hash(), kick.hash());
+    assert!(doc.get_frame(1).is_some());
+
+    // rewrite the source to match
+    let source = "./test/100ms.wav |:10| (./test/100ms.wav) ./test/100ms.wav.bak |:10|";
+    assert_eq!(
+        relink(source, "./test/100ms.wav", "./kick.wav").unwrap(),
+        "./kick.wav |:10| (./test/100ms.wav) ./test/100ms.wav.bak |:10|"
+    );
+    assert!(relink(source, "./test/100ms.wav", "kick.wav").is_err());
+
+    // mute and solo by name or path
+    let set = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+    doc.mute(set(&["100ms"]), set(&[]));
+    assert_eq!(doc.get_frame(1), None);
+    doc.mute(set(&[]), set(&["./test/100ms_inverted.wav"]));
+    assert_eq!(doc.get_frame(1), None);
+    assert!(doc.get_frame(11).is_some());
+}
+
+#[test]
+fn test_18_cue_points () {
+    let media = SoundMap::new();
+    let names: Vec<_> = media.cues("./test/cues.wav").into_iter()
+        .map(|cue| (cue.name, cue.position))
+        .collect();
+    assert_eq!(names, vec![("intro".into(), 0), ("drop".into(), 1000), ("outro".into(), 3000)]);
+    // the frame is the sample offset, many editors leave the position at 0
+    let offsets: Vec<_> = media.cues("./test/cue_offsets.wav").into_iter()
+        .map(|cue| cue.position)
+        .collect();
+    assert_eq!(offsets, vec![0, 1000, 3000]);
+    let doc = eval(read("./test/cue_offsets.wav >drop"));
+    assert_eq!(doc.get_frame(0), Some(media.get_frame("./test/cues.wav", 1000)));
+
+    // from the evaluator
+    let doc = eval(read("./test/cues.wav >drop >outro"));
+    let events: Vec<_> = doc.events.iter().map(|(at, event)| (at, event.len())).collect();
+    assert_eq!(events, vec![(0, 2000), (2000, 1410)]);
+    assert_eq!(doc.get_frame(0), Some(media.get_frame("./test/cues.wav", 1000)));
+
+    // from memory
+    let mut sampler = Sampler::new(2);
+    sampler.load("cues", "./test/cues.wav").unwrap();
+    let triggers = sampler.triggers();
+    assert!(triggers.play("cues", Some("chorus")).is_err());
+    assert!(triggers.play("loops", None).is_err());
+    triggers.play("cues", Some("drop")).unwrap();
+    sampler.process(64);
+    for channel in 0..2 {
+        let mut output = vec![0.0; 64];
+        sampler.add_channel(channel, &mut output);
+        for (index, sample) in output.iter().enumerate() {
+            let expected = media.get_frame("./test/cues.wav", 1000 + index as i64);
+            assert_eq!(*sample, expected[0] as f32 / 32768.0);
+        }
+    }
+
+    // from memory, then from disk
+    let length = N_CUE_BUFFER_FRAMES + 8192;
+    let late = CuePoint { name: "late".into(), position: 2000 };
+    let path = write_ramp("lude_test_18.wav", length, &[late]);
+    let mut sampler = Sampler::new(1);
+    sampler.load("ramp", &path).unwrap();
+    sampler.start(44100);
+    assert!(sampler.load("ramp_2", &path).is_err());
+    sampler.triggers().play("ramp", Some("late")).unwrap();
+    let mut heard = Vec::new();
+    while heard.len() < N_CUE_BUFFER_FRAMES + 4096 {
+        sampler.process(1024);
+        let mut output = vec![0.0; 1024];
+        sampler.add_channel(0, &mut output);
+        heard.extend(output.iter().map(|sample| (sample * 32768.0) as usize));
+        // give the renderer time to stream from behind the buffered frames
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    let expected: Vec<usize> = (2000..2000 + heard.len()).map(|i| i % 30000).collect();
+    assert_eq!(heard, expected);
+}
+
+#[test]
+fn test_19_cue_point_slices () {
+    let media = SoundMap::new();
+    let doc = eval(read("./test/cues.wav|intro:drop|drop+100|outro:|:drop|"));
+    let events: Vec<_> = doc.events.iter().map(|(at, event)| (at, event.len())).collect();
+    assert_eq!(events, vec![(0, 1000), (1000, 100), (1100, 1410), (2510, 1000)]);
+    assert_eq!(doc.get_frame(1000), Some(media.get_frame("./test/cues.wav", 1000)));
+    assert_eq!(doc.get_frame(1100), Some(media.get_frame("./test/cues.wav", 3000)));
+}
+
+#[test]
+#[should_panic(expected = "no cue point named chorus")]
+fn test_20_missing_cue_point () {
+    eval(read("./test/cues.wav|chorus:|"));
+}
+
+#[test]
+fn test_21_rendered_cue_points () {
+    let doc = eval(read("./test/100ms.wav |:10| #verse { |:10| #drop |:10| }*2"));
+    let names = |cues: Vec<CuePoint>| -> Vec<(String, i64)> {
+        cues.into_iter().map(|cue| (cue.name, cue.position)).collect()
+    };
+    let cues = cue_points(&doc, false);
+    assert_eq!(names(cues.clone()), vec![
+        ("verse".into(), 10), ("drop".into(), 20), ("verse_2".into(), 30), ("drop_2".into(), 40)
+    ]);
+    let with_events: Vec<_> = names(cue_points(&doc, true)).into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    assert_eq!(with_events, vec![
+        "100ms", "100ms_2", "verse", "100ms_3", "drop", "100ms_4", "verse_2", "100ms_5", "drop_2"
+    ]);
+
+    let path = std::env::temp_dir().join("lude_test_21.wav");
+    let path = path.to_str().unwrap();
+    let end = doc.duration().unwrap();
+    let options = WriteOptions { cues: cues.clone(), ..WriteOptions::default() };
+    write_to_file(to_frames(to_channels(render(&doc, 0, end), 1)), path, &options);
+    let file = SndFile::new(path, OpenMode::Read).unwrap();
+    assert_eq!(file.get_cues(), cues);
+    assert_eq!(file.get_sndinfo().frames as FrameTime, end + 1);
+}
+
+#[test]
+fn test_22_provenance () {
+    let source = "(kick) ./test/100ms.wav |:10| ./test/100ms_inverted.wav |:10|\n\n#end\n";
+    let doc = eval(read(source));
+    let provenance = Provenance::new(&doc, source);
+    assert_eq!(provenance.sources, vec!["./test/100ms.wav", "./test/100ms_inverted.wav"]);
+    assert_eq!(provenance.source.as_deref(), Some(source));
+    assert_eq!(Provenance::from_comment(&provenance.to_comment()), Ok(provenance.clone()));
+    assert!(Provenance::from_comment("recorded live").is_err());
+    let tampered = provenance.to_comment().replace("#end", "#start");
+    assert!(Provenance::from_comment(&tampered).is_err());
+
+    let path = std::env::temp_dir().join("lude_test_22.wav");
+    let path = path.to_str().unwrap();
+    let end = doc.duration().unwrap();
+    let output = to_frames(to_channels(render(&doc, 0, end), 1));
+    let options = WriteOptions { provenance: Some(provenance.clone()), ..WriteOptions::default() };
+    write_to_file(output, path, &options);
+    assert_eq!(read_provenance(path), Ok(provenance));
+    let file = SndFile::new(path, OpenMode::Read).unwrap();
+    let software = file.get_string(StringSoundType::Software);
+    assert_eq!(software, Some(format!("lude {}", env!("CARGO_PKG_VERSION"))));
+    assert!(read_provenance("./test/100ms.wav").is_err());
+}
+
+
+#[test]
+fn test_23_reverse () {
+    let path = write_ramp("lude_test_23.wav", 1000, &[]);
+    let up = ramp(100, 110);
+    let down: Vec<i16> = up.iter().rev().cloned().collect();
+    assert_eq!(frames(&path, "SRC|100:110|"), up);
+    assert_eq!(frames(&path, "SRC|110-10|"), down);
+    assert_eq!(frames(&path, "SRC|110:100|"), up);
+    assert_eq!(frames(&path, "SRC|100:110 rev|"), down);
+    assert_eq!(frames(&path, "SRC|110-10 rev|"), up);
+    assert_eq!(frames(&path, "SRC|110:100rev|"), down);
+    assert_eq!(frames(&path, "SRC|110-10|100+10|"), [down, up].concat());
+}
+
+#[test]
+fn test_24_wrap_around () {
+    let path = write_ramp("lude_test_24.wav", 1000, &[]);
+    let wrapped = [ramp(995, 1000), ramp(0, 5)].concat();
+    let unwrapped: Vec<i16> = wrapped.iter().rev().cloned().collect();
+    assert_eq!(frames(&path, "SRC|995+10|"), wrapped);
+    assert_eq!(frames(&path, "SRC|5-10|"), unwrapped);
+    assert_eq!(frames(&path, "SRC|995+10 rev|"), unwrapped);
+    assert_eq!(frames(&path, "SRC|5-10 rev|"), wrapped);
+}
+
+#[test]
+fn test_25_frames () {
+    let path = write_ramp("lude_test_25.wav", 1000, &[
+        CuePoint { name: "hit".into(), position: 500 },
+        CuePoint { name: "hit_2".into(), position: 600 }
+    ]);
+    assert_eq!(frames(&path, "SRC|7|8|"), vec![7, 8]);
+    assert_eq!(frames(&path, "SRC|7,3,1000,hit|"), vec![7, 3, 0, 500]);
+    assert_eq!(frames(&path, "SRC|7,3,9 rev|"), vec![9, 3, 7]);
+    assert_eq!(frames(&path, "SRC|7_2,9,3_2|"), vec![7, 7, 9, 3, 3]);
+    assert_eq!(frames(&path, "SRC|hit_2|"), vec![600]);
+    assert_eq!(frames(&path, "SRC|hit _2|"), vec![500, 500]);
+    // silent outside the source, wherever it was last read
+    let media = SoundMap::new();
+    assert_eq!(media.get_frame(&path, 7), vec![7]);
+    assert_eq!(media.get_frame(&path, 1000), vec![0]);
+    assert_eq!(media.get_frame(&path, 5000), vec![0]);
+    assert_eq!(media.get_frame(&path, -1), vec![0]);
+}
+
+#[test]
+fn test_26_source_cursor () {
+    let path = write_ramp("lude_test_26.wav", 1000, &[]);
+    let back: Vec<i16> = ramp(15, 20).into_iter().rev().collect();
+    assert_eq!(frames(&path, "SRC |+10| |+10| |-5|"), [ramp(0, 20), back].concat());
+    assert_eq!(frames(&path, "SRC|100:110|+3|"), ramp(100, 113));
+    assert_eq!(frames(&path, "SRC|100:110 rev|-3|"), ramp(97, 110).into_iter().rev().collect::<Vec<i16>>());
+    assert_eq!(frames(&path, "SRC|7,3|+2|"), vec![7, 3, 4, 5]);
+    assert_eq!(frames(&path, "SRC|-2|"), vec![999, 998]);
+    assert_eq!(frames(&path, "SRC|998:|+2|"), vec![998, 999, 0, 1]);
+}
+
+#[test]
+fn test_27_division () {
+    let path = write_ramp("lude_test_27.wav", 1000, &[]);
+    assert_eq!(frames(&path, "SRC|/10 3|"), ramp(300, 400));
+    assert_eq!(frames(&path, "SRC|/10 3:6|"), ramp(300, 600));
+    assert_eq!(frames(&path, "SRC|/10 8:|"), ramp(800, 1000));
+    assert_eq!(frames(&path, "SRC|/10 3+2|"), ramp(300, 500));
+    assert_eq!(frames(&path, "SRC|/10 0,9_2|"), [ramp(0, 100), ramp(900, 1000), ramp(900, 1000)].concat());
+    assert_eq!(frames(&path, "SRC|/10 3 rev|"), ramp(300, 400).into_iter().rev().collect::<Vec<i16>>());
+    assert_eq!(frames(&path, "SRC|/3 1|").len(), 333);
+    assert_eq!(frames(&path, "SRC|/3 0:3|"), ramp(0, 1000));
+}
+
+#[test]
+fn test_28_relative_positions () {
+    let path = write_ramp("lude_test_28.wav", 1000, &[]);
+    assert_eq!(frames(&path, "SRC|25%:75%|"), ramp(250, 750));
+    assert_eq!(frames(&path, "SRC|12.5%:25%|"), ramp(125, 250));
+    assert_eq!(frames(&path, "SRC|-100:|"), ramp(900, 1000));
+    assert_eq!(frames(&path, "SRC|:-900|"), ramp(0, 100));
+    assert_eq!(frames(&path, "SRC|-100:-90|"), ramp(900, 910));
+    assert_eq!(frames(&path, "SRC|50%+1%|"), ramp(500, 510));
+    assert_eq!(frames(&path, "SRC|-1,50%|"), vec![999, 500]);
+    assert_eq!(frames(&path, "SRC|+3|-2|"), vec![0, 1, 2, 2, 1]);
+}
+
+#[test]
+#[should_panic(expected = "before the start")]
+fn test_29_before_start () {
+    let path = write_ramp("lude_test_29.wav", 1000, &[]);
+    eval(read(&format!("{}|-2000:|", path)));
+}
+
+#[test]
+fn test_30_onsets () {
+    // decaying bursts of a square wave, one every 4000 frames from 1000
+    let mut hits: Vec<i16> = (0..13000).map(|i: usize| match i.checked_sub(1000) {
+        Some(offset) if offset % 4000 < 2000 => {
+            let level = 20000 - (offset % 4000) as i16 * 10;
+            if i & 1 == 0 { level } else { -level }
+        },
+        _ => 0
+    }).collect();
+    let path = write_samples("lude_test_30.wav", &mut hits, &[]);
+    let doc = eval(read(&format!("{}|#1|", path)));
+    assert_eq!(doc.onsets(&path), vec![1000, 5000, 9000]);
+    assert_eq!(doc.hit(&path, 1), (5000, 9000));
+    assert_eq!(doc.hit(&path, 2), (9000, 13000));
+    assert_eq!(doc.length, 4000);
+    let doc = eval(read(&format!("{}|#0:#2|#2_2|/10 1|", path)));
+    assert_eq!(doc.length, 8000 + 2 * 4000 + 1300);
+    let frames = render_mono(&doc);
+    assert_eq!(frames[0], 20000);
+    assert_eq!(frames[8000], 20000);
+    assert_eq!(frames[12000], 20000);
+
+    // a step of 6 dB is only a hit to the most sensitive
+    let step: Vec<f32> = (0..8192).map(|i| if i < 4096 { 0.25 } else { 0.5 }).collect();
+    assert_eq!(onsets::find(&step, 1.0), vec![0, 4096]);
+    assert_eq!(onsets::find(&step, 0.0), vec![0]);
+    let loud = eval(read(&format!("!sensitivity 100 {}|#0|", path)));
+    assert_eq!(loud.sensitivity, 1.0);
+}
+
+#[test]
+#[should_panic(expected = "no onset #3")]
+fn test_31_missing_onset () {
+    let mut hit: Vec<i16> = (0..4000).map(|i| if i >= 1000 { 10000 } else { 0 }).collect();
+    let path = write_samples("lude_test_31.wav", &mut hit, &[]);
+    eval(read(&format!("{}|#3|", path)));
+}
+
+#[test]
+fn test_32_zero_crossings () {
+    // a sawtooth that crosses zero at 50, 99, 150, 199... (mod 100)
+    let mut saw: Vec<i16> = (0..3000).map(|i| ((i % 100) as i16 - 50) * 100).collect();
+    let path = write_samples("lude_test_32.wav", &mut saw, &[]);
+    let events = |source: &str| eval(read(&source.replace("SAW", &path))).events.iter()
+        .map(|(_, event)| (event.offset(0), event.len()))
+        .collect::<Vec<_>>();
+    assert_eq!(events("SAW|1030:1270|"), vec![(1030, 240)]);
+    assert_eq!(events("SAW|1030:1270 snap|"), vec![(1050, 200)]);
+    assert_eq!(events("SAW|1030+240 snap|"), vec![(1050, 200)]);
+    assert_eq!(events("!snap 10 SAW|1030:1270|"), vec![(1030, 240)]);
+    assert_eq!(events("!snap 10 SAW|1045:1255|"), vec![(1050, 200)]);
+    assert_eq!(events("!snap 10 SAW|1045:1255 nosnap|"), vec![(1045, 210)]);
+    assert_eq!(events("!snap 10 SAW|:1255|1045,1046|"), vec![(0, 1250), (1045, 1), (1046, 1)]);
+    assert_eq!(events("!snap 10 SAW|/3 1|"), vec![(999, 1000)]);
+}
+
+#[test]
+fn test_33_fades () {
+    let mut level = vec![10000; 2000];
+    let path = write_samples("lude_test_33.wav", &mut level, &[]);
+    let faded = frames(&path, "SRC|0:1000 <100 >100|");
+    assert_eq!((faded[0], faded[50], faded[100], faded[500]), (0, 5000, 10000, 10000));
+    assert_eq!((faded[949], faded[999]), (5000, 0));
+    assert_eq!(frames(&path, "SRC|0:1000 <100exp|")[50], 2500);
+    assert_eq!(frames(&path, "SRC|0:1000 <100sin|")[50], 7071);
+    assert_eq!(frames(&path, "SRC|0:1000 >100lin rev|")[949], 5000);
+
+    let doc = eval(read(&format!("!crossfade 100 {}|0:1000|1000:2000|", path)));
+    let events: Vec<_> = doc.events.iter().collect();
+    let (first, second) = (events[0].1, events[1].1);
+    assert_eq!((first.len(), first.span(), second.span()), (1000, 1100, 1000));
+    assert_eq!((first.offset(999), first.offset(1050)), (999, 1050));
+    assert_eq!((first.level(0), first.level(1099)), (1.0, 0.0));
+    assert_eq!((second.level(0), second.level(100)), (0.0, 1.0));
+    let doc = eval(read(&format!("!crossfade 100 {}|0:1000| +1 |1000:1999|", path)));
+    assert_eq!(doc.events.iter().map(|(_, event)| event.span()).collect::<Vec<_>>(), vec![1000, 999]);
+}
+
+#[test]
+fn test_34_envelopes () {
+    let mut level = vec![10000; 10000];
+    let path = write_samples("lude_test_34.wav", &mut level, &[]);
+    let stab = frames(&path, "stab = adsr 100 100 50% 200 SRC|0:1000 ~stab|");
+    assert_eq!(stab.len(), 1200);
+    assert_eq!((stab[0], stab[50], stab[100], stab[150], stab[200], stab[999]),
+        (0, 5000, 10000, 7500, 5000, 5000));
+    assert_eq!((stab[1000], stab[1099], stab[1199]), (4975, 2500, 0));
+    assert_eq!(frames(&path, "stab = adsr 100 100 50% 200 SRC ~stab |0:1000|"), stab);
+    let overridden = frames(&path, "stab = adsr 100 100 50% 200 flat = adsr 0 0 100% 0 SRC ~stab |0:1000 ~flat|");
+    assert_eq!((overridden[0], overridden.len()), (10000, 1000));
+    let other = write_samples("lude_test_34_other.wav", &mut vec![10000; 1000], &[]);
+    let unshaped = frames(&path, &format!("stab = adsr 100 100 50% 200 SRC ~stab {}|0:1000|", other));
+    assert_eq!(unshaped[0], 10000);
+}
+
+#[test]
+#[should_panic(expected = "no envelope named stab")]
+fn test_35_missing_envelope () {
+    let path = write_samples("lude_test_35.wav", &mut vec![10000; 1000], &[]);
+    eval(read(&format!("{}|0:100 ~stab|", path)));
+}
+
+#[test]
+fn test_36_gain_and_pan () {
+    let path = write_samples("lude_test_36.wav", &mut vec![10000; 1000], &[]);
+    let first = |source: &str| {
+        let doc = eval(read(&source.replace("SRC", &path)));
+        render(&doc, 0, 0)[0].clone().unwrap()
+    };
+    assert_eq!(first("SRC|-6dB|"), vec![5012]);
+    assert_eq!(first("SRC|0:100 *0.5|"), vec![5000]);
+    assert_eq!(first("SRC|0:100 +6dB *0.5|"), vec![9976]);
+    // a panned slice makes the document stereo,
+    // and unpanned mono slices are heard in both channels
+    let doc = eval(read(&format!("{}|0:100 pan=-1| |0:100 pan=0| |0:100|", path)));
+    assert_eq!(doc.channels(), 2);
+    let output = to_frames(to_channels(render(&doc, 0, doc.length - 1), doc.channels()));
+    let out = std::env::temp_dir().join("lude_test_36_output.wav");
+    let out = out.to_str().unwrap();
+    let options = WriteOptions { channels: doc.channels(), ..WriteOptions::default() };
+    write_to_file(output, out, &options);
+    let mut file = SndFile::new(out, OpenMode::Read).unwrap();
+    assert_eq!((file.get_sndinfo().channels, file.get_sndinfo().frames), (2, 300));
+    let mut written = vec![0; 600];
+    assert_eq!(file.readf_i16(&mut written, 300), 300);
+    let expected = [[10000, 0].repeat(100), [7071, 7071].repeat(100), [10000, 10000].repeat(100)];
+    assert_eq!(written, expected.concat());
+    assert_eq!(first("!panlaw 6 SRC|0:100 pan=0|"), vec![5000, 5000]);
+    assert_eq!(first("!panlaw 0 SRC|0:100 pan=0.5|"), vec![5000, 10000]);
+    let mut doc = Document::new();
+    for value in [-3.0, 3.5, 2.0, 9.0].iter() {
+        assert!(doc.set("panlaw", *value).is_err());
+    }
+    assert_eq!(doc.set("panlaw", 6.0), Ok(()));
+    // gains are not taken for the end of a slice or its length
+    let doc = eval(read(&format!("{}|100: -6dB|", path)));
+    assert_eq!((doc.length, render(&doc, 0, 0)[0].clone()), (900, Some(vec![5012])));
+    let doc = eval(read(&format!("{}|100+3dB|", path)));
+    assert_eq!((doc.length, render(&doc, 0, 0)[0].clone()), (1, Some(vec![14125])));
+    assert_eq!(eval(read(&format!("{}|100:-6.5dB|", path))).length, 900);
+}
+
+#[test]
+#[should_panic(expected = "pan must be between -1 and 1")]
+fn test_37_pan_out_of_range () {
+    let path = write_samples("lude_test_37.wav", &mut vec![10000; 1000], &[]);
+    eval(read(&format!("{}|0:100 pan=2|", path)));
+}
+
+#[test]
+fn test_38_absolute_slices () {
+    let path = write_ramp("lude_test_38.wav", 1000, &[]);
+    assert_eq!(frames(&path, "SRC|200:100|"), ramp(100, 200));
+    // only |x+n| and |x-n| slices wrap around
+    assert_eq!(frames(&path, "SRC|990:1010|"), [ramp(990, 1000), vec![0; 10]].concat());
+    assert_eq!(frames(&path, "SRC|1005,999|"), vec![0, 999]);
+}
+
+#[test]
+#[should_panic(expected = "loop can not be played 0 times (line 2, column 9)")]
+fn test_39_loop_played_0_times () {
+    eval(read("./test/100ms.wav\n{ |:10| }*0"));
+}
+
+#[test]
+#[should_panic(expected = "loop at line 1, column 18 is never closed")]
+fn test_40_unclosed_loop () {
+    eval(read("./test/100ms.wav { |:10|"));
+}
+
+#[test]
+fn test_41_too_many_cue_points () {
+    let cues: Vec<CuePoint> = (0..MAX_CUES + 5)
+        .map(|i| CuePoint { name: format!("hit{}", i), position: i as i64 })
+        .collect();
+    let path = std::env::temp_dir().join("lude_test_41.wav");
+    let path = path.to_str().unwrap();
+    let mut file = SndFile::new_with_info(path, OpenMode::Write, Box::new(SndInfo {
+        frames: 0,
+        samplerate: 44100,
+        channels: 1,
+        format: FormatType::FormatWav as i32 | FormatType::FormatPcm16 as i32,
+        sections: 0,
+        seekable: 0
+    })).unwrap();
+    assert!(file.set_cues(&cues).is_err());
+    file.close();
+
+    // the first ones are written, the rest dropped
+    let options = WriteOptions { cues: cues.clone(), ..WriteOptions::default() };
+    write_to_file(vec![vec![0]; 1000], path, &options);
+    let file = SndFile::new(path, OpenMode::Read).unwrap();
+    assert_eq!(file.get_cues(), &cues[..MAX_CUES]);
+}
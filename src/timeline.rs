@@ -1,32 +1,132 @@
 use std::collections::BTreeMap;
+use std::ops::RangeBounds;
+use crate::types::FrameTime;
 
-pub type Moment = u128;
+pub type Moment = FrameTime;
 pub type EventMap<E> = BTreeMap<Moment, Vec<E>>;
 
-#[derive(Debug)]
+/// Something that takes up time on a `Timeline`.
+pub trait Span {
+    /// How long it lasts; nothing by default.
+    fn span (&self) -> Moment { 0 }
+}
+
+/// A collection of events, each starting at a `Moment`.
+/// Events that start at the same moment are kept in the order
+/// they were added.
+#[derive(Debug, Clone)]
 pub struct Timeline<E> {
-    events: EventMap<E>,
-    pub start: Moment,
-    pub end: Moment,
+    events:  EventMap<E>,
+    count:   usize,
+    /// The span of the longest event that was ever added.
+    /// Determines how far back `overlapping` has to look.
+    longest: Moment,
+}
+
+impl<E: Span> Default for Timeline<E> {
+    fn default () -> Timeline<E> {
+        Timeline::new()
+    }
 }
 
-impl<E> Timeline<E> {
+impl<E: Span> Timeline<E> {
+    /// Creates an empty timeline.
     pub fn new () -> Timeline<E> {
-        Timeline { events: BTreeMap::new(), start: 0, end: 0 }
-    }
-    pub fn add (&mut self, index: u128, event: E) {
-        match self.events.get_mut(&index) {
-            Some(events) => {
-                events.push(event)
-            }
-            None => {
-                let mut events = Vec::new();
-                events.push(event);
-                self.events.insert(index, events);
-            }
-        }
+        Timeline { events: BTreeMap::new(), count: 0, longest: 0 }
     }
+    /// Adds `event` at `index`, after the events already there.
+    pub fn add (&mut self, index: Moment, event: E) {
+        self.longest = self.longest.max(event.span());
+        self.count += 1;
+        self.events.entry(index).or_default().push(event)
+    }
+    /// Gets the events that start at `step`.
     pub fn get (&self, step: Moment) -> Option<&Vec<E>> {
         self.events.get(&step)
     }
+    /// Gets the number of events.
+    pub fn len (&self) -> usize {
+        self.count
+    }
+    /// Returns `true` if there are no events.
+    pub fn is_empty (&self) -> bool {
+        self.count == 0
+    }
+    /// Gets the moment of the first event.
+    pub fn first (&self) -> Option<Moment> {
+        self.events.keys().next().cloned()
+    }
+    /// Gets the moment of the last event to start.
+    pub fn last (&self) -> Option<Moment> {
+        self.events.keys().next_back().cloned()
+    }
+    /// Gets the span of the longest event that was ever added.
+    pub fn longest (&self) -> Moment {
+        self.longest
+    }
+    /// Iterates over all events, in order.
+    pub fn iter (&self) -> impl Iterator<Item = (Moment, &E)> {
+        self.range(..)
+    }
+    /// Iterates mutably over all events, in order.
+    /// Their spans must not grow, or `overlapping` may miss them.
+    pub fn iter_mut (&mut self) -> impl Iterator<Item = (Moment, &mut E)> {
+        self.events.iter_mut().flat_map(|(moment, events)| {
+            events.iter_mut().map(move |event| (*moment, event))
+        })
+    }
+    /// Iterates over the events that start in `range`, in order.
+    pub fn range<R: RangeBounds<Moment>> (
+        &self, range: R
+    ) -> impl Iterator<Item = (Moment, &E)> {
+        self.events.range(range).flat_map(|(moment, events)| {
+            events.iter().map(move |event| (*moment, event))
+        })
+    }
+    /// Iterates over the events that are going on at `moment`,
+    /// i.e. that started at or before it and have not ended yet.
+    pub fn overlapping (&self, moment: Moment) -> impl Iterator<Item = (Moment, &E)> {
+        let start = moment.saturating_sub(self.longest);
+        self.range(start..=moment)
+            .filter(move |(start, event)| moment - start < event.span().max(1))
+    }
+    /// Removes the events at `moment` for which `matches` returns `true`.
+    /// Returns what was removed.
+    pub fn remove<F: Fn(&E) -> bool> (&mut self, moment: Moment, matches: F) -> Vec<E> {
+        let events = match self.events.remove(&moment) {
+            Some(events) => events,
+            None => return Vec::new()
+        };
+        let (removed, kept): (Vec<E>, Vec<E>) = events.into_iter().partition(|e| matches(e));
+        if !kept.is_empty() { self.events.insert(moment, kept); }
+        self.count -= removed.len();
+        removed
+    }
+    /// Removes all events that start before `moment`, in order.
+    pub fn drain_before (&mut self, moment: Moment) -> Vec<(Moment, E)> {
+        let later = self.events.split_off(&moment);
+        let earlier = std::mem::replace(&mut self.events, later);
+        let drained: Vec<(Moment, E)> = earlier.into_iter()
+            .flat_map(|(moment, events)| {
+                events.into_iter().map(move |event| (moment, event))
+            })
+            .collect();
+        self.count -= drained.len();
+        drained
+    }
+    /// Removes all events.
+    pub fn clear (&mut self) {
+        self.events.clear();
+        self.count = 0;
+    }
+    /// Moves the events at `from` for which `matches` returns `true`
+    /// to `to`. Returns how many were moved.
+    pub fn move_to<F: Fn(&E) -> bool> (
+        &mut self, from: Moment, to: Moment, matches: F
+    ) -> usize {
+        let moved = self.remove(from, matches);
+        let count = moved.len();
+        for event in moved { self.add(to, event) }
+        count
+    }
 }
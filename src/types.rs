@@ -1,6 +1,10 @@
 /// A point in time.
 pub type FrameTime = usize;
 
+/// Frames per second of the output.
+/// **TODO** Allow output sample rate to be set.
+pub const SAMPLE_RATE: usize = 44100;
+
 /// An amplitude value.
 pub type Sample = i16;
 
@@ -19,6 +23,9 @@ pub type Chunk = Vec<Option<Frame>>;
 /// **TODO** Following a slice with `&` prevents the cursor from advancing.
 /// This allows multiple slices to be triggered simultaneously.
 ///
+/// Consecutive slices can share their bars, as in `|x:y|z:w|`,
+/// or be separated by whitespace, as in `|x:y| |z:w|`.
+///
 /// The following slices are available:
 ///
 /// * The `||` slice writes the full source.
@@ -28,19 +35,224 @@ pub type Chunk = Vec<Option<Frame>>;
 /// * The `|:y| slice writes the part of the source
 ///   from the start of the source up to `y` frames into the source.
 /// * The `|x:y|` slice writes the part of the source
-///   between `x` and `y`.
+///   between `x` and `y`, forwards even if `y` comes before `x`.
 /// * The `|x+n|` slice writes the part of the source
 ///   between `x` and `x+n`.
-/// * The `|x-n|` slice writes the part of the source
-///   between `x` and `x-n`.
-/// * **TODO** wrap slices around
-/// * **TODO** source cursor for `|+n|` / `|-n|` (or are those jump/skip?)
-/// * **TODO** The `|x|` slice writes one frame of the source.
-/// * **TODO** The `|x,y,...| slice writes individual frames.
-/// * **TODO** The `|x_n|` slice writes the `x`th frame `n` times
+/// * The `|x-n|` slice writes the `n` frames before `x` backwards,
+///   i.e. the part of the source between `x` and `x-n`.
+/// * `|x+n|` and `|x-n|` slices which reach beyond either end of the source
+///   wrap around to its other end. Other slices are silent beyond its end.
+/// * The `|x|` slice writes one frame of the source.
+/// * The `|x_n|` slice writes the `x`th frame `n` times.
+/// * The `|x,y,...|` slice writes individual frames, one after the other.
+///   Each of them can be repeated like above, e.g. `|x_n,y,z_m|`.
+/// * In all of these, `x` and `y` can also be the name of a **cue point**
+///   in the source, such as `|intro:drop|` or `|verse+44100|`. Cue points
+///   are read from the source file whenever the document is evaluated,
+///   so moving them in an editor moves every slice that uses them.
+///   To repeat the frame at a cue point, separate the count with a space,
+///   as in `|verse _4|`, since `|verse_4|` names the cue point `verse_4`.
+/// * Writing `rev` after the bounds of any slice, such as `|100:200 rev|`
+///   or `|rev|`, writes it the other way round. Cue points with the name
+///   of a modifier can not be used in slices.
+/// * Instead of a number of frames from its start, any point in the source
+///   can also be given as a percentage of its length, as in `|25%:75%|`,
+///   or as a number of frames before its end, as in `|-4410:|`, which
+///   writes the last 100 ms. This way the same slices fit sources of
+///   different lengths. `|x+n|` and `|x-n|` slices can also take their
+///   length as a percentage, e.g. `|50%+10%|`. Since `|-n|` is already
+///   taken, write `|-n:-m|` rather than `|-n|` for a single frame
+///   before the end.
+/// * `#n` stands for the `n`th **onset** of the source, counting from 0,
+///   i.e. where its `n+1`th hit starts, as detected from its loudness
+///   (see `media::onsets`). `|#3|` writes the 4th hit up to the next one,
+///   `|#3:#5|` the 4th and 5th, and `|#3,#0_2|` the 4th, then the 1st
+///   twice. `!sensitivity` sets how readily hits are detected, and
+///   `lude info --onsets` lists them.
+/// * Writing `snap` after the bounds of a slice, as in `|1000:2000 snap|`,
+///   moves them to the nearest points where the source crosses zero,
+///   so that the slice does not click. They are moved at most as far
+///   as `!snap NUMBER` says, or 10 ms if it is not set. `!snap NUMBER`
+///   also snaps every slice after it, unless it says `nosnap`.
+///   The start and end of the source are never moved.
+/// * Writing `<n` after the bounds of a slice fades it in over `n` frames,
+///   and `>n` fades it out over its last `n` frames, as in `|0:5000 <441 >882|`.
+///   The level rises in a straight line unless a curve follows the length:
+///   `lin`, `sin` or `exp`, as in `>882exp`. `!crossfade NUMBER` makes
+///   every slice that starts where the previous one of the same source ends
+///   fade in over `NUMBER` frames, while the previous one keeps playing on
+///   for as long and fades out.
+/// * Writing `~NAME` after the bounds of a slice shapes it with the
+///   envelope `NAME` (see `Envelope`), as in `|0:4410 ~stab|`. Its release
+///   plays on past the end of the slice. To shape every following slice
+///   of the active source, write `~NAME` on its own after the source.
+/// * Writing a gain after the bounds of a slice makes it louder or
+///   quieter: in dB, as in `|0:4410 -6dB|` or `|+3dB|`, or as a factor,
+///   as in `|*0.5|`. A gain is never taken for a bound, so `|100+3dB|`
+///   writes frame 100 3 dB louder. Writing `pan=` and a number from -1 (left) to 1
+///   (right) places it between the channels, as in `|0:4410 pan=-0.5|`;
+///   a mono source that is panned is heard in both. `!panlaw` sets how.
+/// * Starting a slice with `/n` divides the source into `n` equal parts,
+///   and counts its bounds in parts instead of frames: `|/16 3|` writes
+///   the 4th of 16 parts, `|/16 3:6|` the 4th to the 6th, `|/16 0,4,8|`
+///   the 1st, 5th and 9th, and `|/16 3+2|` two parts from the 4th.
+///   This chops a loop into beats whatever its exact length is.
+///   Such slices only take part numbers.
+/// * Every source keeps track of where the last slice of it stopped.
+///   The `|+n|` slice writes the `n` frames after that point, and the
+///   `|-n|` slice the `n` frames before it, backwards, so that e.g.
+///   `|+11025| |+11025| |-5512|` walks forward and back through the source.
+///   Before the first slice of a source, that point is its start.
 pub enum SliceType {
     Full,
     Abs,
     Fwd,
     Rew
 }
+
+/// Changes to how a slice is written, given around its bounds.
+#[derive(Debug, Clone)]
+pub struct Modifiers {
+    /// Whether the slice is written the other way round (`rev`).
+    pub reverse: bool,
+    /// Into how many equal parts the source is divided (`/n`), if at all.
+    /// The bounds of the slice are then counted in parts, not frames.
+    pub division: Option<FrameTime>,
+    /// Whether the bounds of the slice are moved to zero crossings
+    /// (`snap`) or not (`nosnap`), if not as the document says (`!snap`).
+    pub snap: Option<bool>,
+    /// How the slice fades in (`<n`), if at all.
+    pub fade_in: Option<Fade>,
+    /// How the slice fades out (`>n`), if at all.
+    pub fade_out: Option<Fade>,
+    /// The envelope that shapes the slice (`~NAME`), if any.
+    pub envelope: Option<Envelope>,
+    /// What the slice is multiplied by (`-6dB`, `*0.5`).
+    pub gain: f64,
+    /// Where the slice is placed between the left (-1)
+    /// and right (1) channels (`pan=`), if anywhere.
+    pub pan: Option<f64>,
+}
+
+impl Default for Modifiers {
+    fn default () -> Modifiers {
+        Modifiers {
+            reverse:  false,
+            division: None,
+            snap:     None,
+            fade_in:  None,
+            fade_out: None,
+            envelope: None,
+            gain:     1.0,
+            pan:      None
+        }
+    }
+}
+
+/// How loud a panned source is in the left and right channels,
+/// named after how much it is turned down in the center.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PanLaw {
+    /// The far side is turned down, the near side is not (`!panlaw 0`).
+    ZeroDb,
+    /// Both sides follow a quarter sine, so that the
+    /// loudness stays the same (`!panlaw 3`).
+    ThreeDb,
+    /// Both sides follow a straight line, so that the
+    /// amplitude stays the same (`!panlaw 6`).
+    SixDb,
+}
+
+impl PanLaw {
+    /// Gets the levels of the left and right channels of a source
+    /// that is panned to `pan`, from -1 (left) to 1 (right).
+    pub fn levels (self, pan: f64) -> (f64, f64) {
+        let right = (pan.clamp(-1.0, 1.0) + 1.0) / 2.0;
+        let left = 1.0 - right;
+        match self {
+            PanLaw::ZeroDb => ((2.0 * left).min(1.0), (2.0 * right).min(1.0)),
+            PanLaw::ThreeDb => (
+                (left * std::f64::consts::FRAC_PI_2).sin(),
+                (right * std::f64::consts::FRAC_PI_2).sin()
+            ),
+            PanLaw::SixDb => (left, right)
+        }
+    }
+}
+
+/// How the level changes over the course of a fade.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Curve {
+    /// In a straight line (`lin`).
+    Linear,
+    /// Along a quarter sine (`sin`), so that two of them crossfade
+    /// without a dip in loudness.
+    Sine,
+    /// Slowly at first, then quickly (`exp`).
+    Exponential,
+}
+
+impl Curve {
+    /// Gets the curve named `name` in source code.
+    pub fn from_name (name: &str) -> Option<Curve> {
+        match name {
+            "lin" => Some(Curve::Linear),
+            "sin" => Some(Curve::Sine),
+            "exp" => Some(Curve::Exponential),
+            _ => None
+        }
+    }
+    /// Gets the level at `progress`, from 0 (silent) to 1 (full).
+    pub fn level (self, progress: f64) -> f64 {
+        let progress = progress.clamp(0.0, 1.0);
+        match self {
+            Curve::Linear => progress,
+            Curve::Sine => (progress * std::f64::consts::FRAC_PI_2).sin(),
+            Curve::Exponential => progress * progress
+        }
+    }
+}
+
+/// An **envelope** that shapes the level of a slice: it rises to full over
+/// `attack` frames, falls to `sustain` over `decay` frames, stays there
+/// until the slice ends, then falls silent over `release` frames while
+/// the source plays on. Declared as `NAME = adsr ATTACK DECAY SUSTAIN% RELEASE`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Envelope {
+    /// How many frames the level takes to rise to full.
+    pub attack:  FrameTime,
+    /// How many frames the level then takes to fall to `sustain`.
+    pub decay:   FrameTime,
+    /// The level while the slice is held, from 0 to 1.
+    pub sustain: f64,
+    /// How many frames after the slice ends the level takes to fall to 0.
+    pub release: FrameTime,
+}
+
+impl Envelope {
+    /// Gets the level `index` frames after a slice that is held
+    /// for `held` frames starts, from 0 (silent) to 1 (full).
+    pub fn level (&self, index: FrameTime, held: FrameTime) -> f64 {
+        if index < held { return self.held_level(index) }
+        let released = (index - held + 1) as f64 / self.release.max(1) as f64;
+        self.held_level(held) * (1.0 - released).max(0.0)
+    }
+    fn held_level (&self, index: FrameTime) -> f64 {
+        if index < self.attack {
+            index as f64 / self.attack as f64
+        } else if index < self.attack + self.decay {
+            1.0 - (1.0 - self.sustain) * (index - self.attack) as f64 / self.decay as f64
+        } else {
+            self.sustain
+        }
+    }
+}
+
+/// A fade in or out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fade {
+    /// How many frames it lasts.
+    pub len:   FrameTime,
+    /// How the level changes.
+    pub curve: Curve,
+}